@@ -0,0 +1,31 @@
+//! Builds and runs a program with the `make_runtime!` macro instead of assembly.
+
+use simple_vm::make_runtime;
+
+fn main() {
+    let mut rt = make_runtime! {
+        .constants = [];
+        .procs = [
+            .{ // [0]: main()
+                alloc(1);
+                movv(0, 10);
+                call(1);
+                print_s64(0);
+                hlt();
+            },
+            .{ // [1]: factorial(n)
+                alloc(1);                               // {a}
+                bnz(-1, 1 + 2 + 8 + 1);                 // if (n == 0)
+                movv(-1, 1);                            // return 1
+                ret();
+                subsi(0, -1, 1);                        // a = n - 1
+                call(1);                                // a = factorial(a)
+                muls(-1, -1, 0);                        // return n * a
+                ret();
+            }
+        ];
+    };
+    if let Err(trap) = rt.call(0).and_then(|()| rt.run()) {
+        eprintln!("trap: {trap}");
+    }
+}
@@ -0,0 +1,548 @@
+//! Assembler for the textual `.sasm` format.
+//!
+//! The syntax by example, from `examples/countdown.sasm`:
+//!
+//! ```text
+#![doc = include_str!("../examples/countdown.sasm")]
+//! ```
+//!
+//! Mnemonics and operand types are taken from the opcode table in [crate::opcodes].
+//! Branch offsets accept labels, `call`/`ldp` accept proc names and `ldc` accepts constant
+//! names. Every operand also accepts a plain integer.
+//!
+//! `hcall` accepts the name of a host function, which is added to [Module::hosts] on first
+//! use.
+//!
+//! Exception handlers are declared inside a proc with labels for the start and end of the
+//! region and the handler, followed by the slot receiving the thrown value:
+//!
+//! ```text
+//! .proc main
+//!     alloc 1
+//!     .catch try, done, failed, 0
+//! try:
+//!     call parse
+//! done:
+//!     ret
+//! failed:
+//!     print_str 0
+//!     ret
+//! .end
+//! ```
+//!
+//! Handlers are searched in the order they are declared, so inner regions come first.
+
+pub mod lexer;
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    module::Module,
+    opcodes::{self, OpcodeInfo, OperandKind, OperandType},
+    runtime::{
+        proc::{Handler, Proc},
+        Constant,
+    },
+    util::Write,
+};
+
+use self::lexer::{Token, TokenKind};
+
+/// An error at a 1-based line and column
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Assembles `src` into a [Module]
+///
+/// All diagnostics are collected before returning.
+pub fn assemble(src: &str) -> Result<Module, Vec<Diagnostic>> {
+    let mut asm = Assembler::default();
+    for (index, line) in src.lines().enumerate() {
+        asm.parse_line(index + 1, line);
+    }
+    if let Some(proc) = asm.current.take() {
+        asm.error(proc.line, proc.column, "missing `.end` for `.proc`");
+        asm.procs.push(proc);
+    }
+    asm.finish()
+}
+
+#[derive(Default)]
+struct Assembler<'a> {
+    diagnostics: Vec<Diagnostic>,
+    constants: Vec<Constant>,
+    constant_names: HashMap<&'a str, u32>,
+    procs: Vec<ProcSource<'a>>,
+    proc_names: HashMap<&'a str, u32>,
+    /// Host functions in order of first use
+    hosts: Vec<&'a str>,
+    /// The proc currently being parsed
+    current: Option<ProcSource<'a>>,
+    entry: Option<(usize, Token<'a>)>,
+}
+
+struct ProcSource<'a> {
+    name: &'a str,
+    line: usize,
+    column: usize,
+    insns: Vec<InsnSource<'a>>,
+    /// Label name to instruction index
+    labels: HashMap<&'a str, usize>,
+    catches: Vec<CatchSource<'a>>,
+}
+
+/// A `.catch` directive with the labels of the region start and end and of the handler
+struct CatchSource<'a> {
+    line: usize,
+    labels: [Token<'a>; 3],
+    slot: i16,
+}
+
+struct InsnSource<'a> {
+    info: &'static OpcodeInfo,
+    line: usize,
+    operands: Vec<Token<'a>>,
+}
+
+impl<'a> Assembler<'a> {
+    fn error(&mut self, line: usize, column: usize, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            line,
+            column,
+            message: message.into(),
+        });
+    }
+
+    fn parse_line(&mut self, line: usize, text: &'a str) {
+        let tokens = match lexer::tokenize(text) {
+            Ok(tokens) => tokens,
+            Err(err) => return self.error(line, err.column, err.message),
+        };
+        let mut tokens = &tokens[..];
+        // Labels
+        while let [Token {
+            kind: TokenKind::Ident(name),
+            column,
+        }, Token {
+            kind: TokenKind::Colon,
+            ..
+        }, rest @ ..] = tokens
+        {
+            tokens = rest;
+            let Some(proc) = &mut self.current else {
+                self.error(line, *column, "label outside of `.proc`");
+                continue;
+            };
+            let index = proc.insns.len();
+            if proc.labels.insert(name, index).is_some() {
+                self.error(line, *column, format!("duplicate label `{name}`"));
+            }
+        }
+        match tokens {
+            [] => {}
+            [Token {
+                kind: TokenKind::Directive(directive),
+                column,
+            }, args @ ..] => self.parse_directive(line, *column, directive, args),
+            [Token {
+                kind: TokenKind::Ident(mnemonic),
+                column,
+            }, args @ ..] => self.parse_insn(line, *column, mnemonic, args),
+            [token, ..] => self.error(line, token.column, "expected instruction or directive"),
+        }
+    }
+
+    fn parse_directive(
+        &mut self,
+        line: usize,
+        column: usize,
+        directive: &'a str,
+        args: &[Token<'a>],
+    ) {
+        match (directive, args) {
+            (
+                "const",
+                [Token {
+                    kind: TokenKind::Ident(name),
+                    column: name_column,
+                }, value],
+            ) => {
+                let constant = match value.kind {
+                    TokenKind::Int(int) => match i64::try_from(int) {
+                        Ok(int) => Constant::S64(int),
+                        Err(_) => {
+                            let message = format!("constant `{int}` out of range for i64");
+                            return self.error(line, value.column, message);
+                        }
+                    },
+                    TokenKind::Float(value) => Constant::F64(value),
+                    TokenKind::Str(raw) => match lexer::unescape(raw) {
+                        Ok(value) => Constant::Str(value),
+                        Err(message) => return self.error(line, value.column, message),
+                    },
+                    _ => return self.error(line, value.column, "expected number or string"),
+                };
+                let index = self.constants.len() as u32;
+                if self.constant_names.insert(name, index).is_some() {
+                    self.error(line, *name_column, format!("duplicate constant `{name}`"));
+                }
+                self.constants.push(constant);
+            }
+            ("const", _) => self.error(line, column, "expected `.const <name> <value>`"),
+            (
+                "entry",
+                [name @ Token {
+                    kind: TokenKind::Ident(_),
+                    ..
+                }],
+            ) => {
+                if self.entry.is_some() {
+                    self.error(line, column, "duplicate `.entry`");
+                }
+                self.entry = Some((line, *name));
+            }
+            ("entry", _) => self.error(line, column, "expected `.entry <proc>`"),
+            (
+                "proc",
+                [Token {
+                    kind: TokenKind::Ident(name),
+                    column: name_column,
+                }],
+            ) => {
+                if let Some(proc) = self.current.take() {
+                    self.error(proc.line, proc.column, "missing `.end` for `.proc`");
+                    self.procs.push(proc);
+                }
+                let index = self.procs.len() as u32;
+                if self.proc_names.insert(name, index).is_some() {
+                    self.error(line, *name_column, format!("duplicate proc `{name}`"));
+                }
+                self.current = Some(ProcSource {
+                    name,
+                    line,
+                    column,
+                    insns: Vec::new(),
+                    labels: HashMap::new(),
+                    catches: Vec::new(),
+                });
+            }
+            ("proc", _) => self.error(line, column, "expected `.proc <name>`"),
+            (
+                "catch",
+                [start, Token {
+                    kind: TokenKind::Comma,
+                    ..
+                }, end, Token {
+                    kind: TokenKind::Comma,
+                    ..
+                }, target, Token {
+                    kind: TokenKind::Comma,
+                    ..
+                }, Token {
+                    kind: TokenKind::Int(slot),
+                    column: slot_column,
+                }],
+            ) if [start, end, target]
+                .iter()
+                .all(|token| matches!(token.kind, TokenKind::Ident(_))) =>
+            {
+                let Ok(slot) = i16::try_from(*slot) else {
+                    return self.error(line, *slot_column, format!("slot out of range: {slot}"));
+                };
+                let Some(proc) = &mut self.current else {
+                    return self.error(line, column, "`.catch` outside of `.proc`");
+                };
+                proc.catches.push(CatchSource {
+                    line,
+                    labels: [*start, *end, *target],
+                    slot,
+                });
+            }
+            ("catch", _) => self.error(
+                line,
+                column,
+                "expected `.catch <start>, <end>, <handler>, <slot>`",
+            ),
+            ("end", []) => match self.current.take() {
+                Some(proc) => self.procs.push(proc),
+                None => self.error(line, column, "`.end` without `.proc`"),
+            },
+            ("end", _) => self.error(line, column, "expected `.end`"),
+            _ => self.error(line, column, format!("unknown directive `.{directive}`")),
+        }
+    }
+
+    fn parse_insn(&mut self, line: usize, column: usize, mnemonic: &str, args: &[Token<'a>]) {
+        let Some(info) = opcodes::lookup(mnemonic) else {
+            return self.error(line, column, format!("unknown mnemonic `{mnemonic}`"));
+        };
+        // Operands are separated by commas
+        let mut operands = Vec::new();
+        for (index, token) in args.iter().enumerate() {
+            let is_comma = token.kind == TokenKind::Comma;
+            if is_comma != (index % 2 == 1) {
+                return self.error(line, token.column, "expected `,` between operands");
+            }
+            if !is_comma {
+                operands.push(*token);
+            }
+        }
+        if args
+            .last()
+            .is_some_and(|token| token.kind == TokenKind::Comma)
+        {
+            return self.error(line, args[args.len() - 1].column, "trailing `,`");
+        }
+        if operands.len() != info.operands.len() {
+            return self.error(
+                line,
+                column,
+                format!(
+                    "`{mnemonic}` expects {} operand(s), found {}",
+                    info.operands.len(),
+                    operands.len()
+                ),
+            );
+        }
+        let Some(proc) = &mut self.current else {
+            return self.error(line, column, "instruction outside of `.proc`");
+        };
+        proc.insns.push(InsnSource {
+            info,
+            line,
+            operands,
+        });
+    }
+
+    fn finish(mut self) -> Result<Module, Vec<Diagnostic>> {
+        let mut module = Module::new();
+        let procs = std::mem::take(&mut self.procs);
+        for proc in &procs {
+            module.procs.push(self.encode_proc(proc));
+        }
+        module.entry = match self.entry {
+            Some((line, token)) => {
+                let TokenKind::Ident(name) = token.kind else {
+                    unreachable!();
+                };
+                let entry = self.proc_names.get(name).copied();
+                if entry.is_none() {
+                    self.error(line, token.column, format!("unknown proc `{name}`"));
+                }
+                entry
+            }
+            None => self.proc_names.get("main").copied(),
+        };
+        module.constants = self.constants;
+        module.hosts = self.hosts.iter().map(|name| name.to_string()).collect();
+        if !self.diagnostics.is_empty() {
+            self.diagnostics
+                .sort_by_key(|diag| (diag.line, diag.column));
+            return Err(self.diagnostics);
+        }
+        Ok(module)
+    }
+
+    fn encode_proc(&mut self, proc: &ProcSource<'a>) -> Proc {
+        // All instructions have a fixed size, so label offsets are known upfront
+        let mut offsets = Vec::with_capacity(proc.insns.len() + 1);
+        let mut offset = 0;
+        for insn in &proc.insns {
+            offsets.push(offset);
+            offset += insn.info.size();
+        }
+        offsets.push(offset);
+        let mut code = Vec::with_capacity(offset);
+        for (index, insn) in proc.insns.iter().enumerate() {
+            code.write_u8(insn.info.opcode);
+            for (operand, token) in insn.info.operands.iter().zip(&insn.operands) {
+                let value = match (token.kind, operand.kind) {
+                    (TokenKind::Float(value), _) if operand.ty == OperandType::F64 => {
+                        Some(value.to_bits() as i64 as i128)
+                    }
+                    (TokenKind::Int(value), _) if operand.ty == OperandType::F64 => {
+                        Some((value as f64).to_bits() as i64 as i128)
+                    }
+                    (TokenKind::Int(value), _) => Some(value),
+                    (TokenKind::Ident(name), OperandKind::Offset) => match proc.labels.get(name) {
+                        Some(&target) => Some(offsets[target] as i128 - offsets[index + 1] as i128),
+                        None => {
+                            self.error(insn.line, token.column, format!("unknown label `{name}`"));
+                            None
+                        }
+                    },
+                    (TokenKind::Ident(name), OperandKind::Proc) => {
+                        let proc = self.proc_names.get(name).copied();
+                        if proc.is_none() {
+                            self.error(insn.line, token.column, format!("unknown proc `{name}`"));
+                        }
+                        proc.map(i128::from)
+                    }
+                    (TokenKind::Ident(name), OperandKind::Host) => {
+                        let index = match self.hosts.iter().position(|host| *host == name) {
+                            Some(index) => index,
+                            None => {
+                                self.hosts.push(name);
+                                self.hosts.len() - 1
+                            }
+                        };
+                        Some(index as i128)
+                    }
+                    (TokenKind::Ident(name), OperandKind::Constant) => {
+                        let constant = self.constant_names.get(name).copied();
+                        if constant.is_none() {
+                            let message = format!("unknown constant `{name}`");
+                            self.error(insn.line, token.column, message);
+                        }
+                        constant.map(i128::from)
+                    }
+                    _ if operand.ty == OperandType::F64 => {
+                        let message = format!("expected number for operand `{}`", operand.name);
+                        self.error(insn.line, token.column, message);
+                        None
+                    }
+                    _ => {
+                        let message = format!("expected integer for operand `{}`", operand.name);
+                        self.error(insn.line, token.column, message);
+                        None
+                    }
+                };
+                let (min, max) = operand.ty.range();
+                let value = match value {
+                    Some(value) if (min as i128..=max as i128).contains(&value) => value as i64,
+                    Some(value) => {
+                        let message = format!(
+                            "operand `{}` of `{}` out of range for {}: {value}",
+                            operand.name, insn.info.mnemonic, operand.ty
+                        );
+                        self.error(insn.line, token.column, message);
+                        0
+                    }
+                    None => 0,
+                };
+                operand.ty.write(&mut code, value);
+            }
+        }
+        let mut handlers = Vec::with_capacity(proc.catches.len());
+        for catch in &proc.catches {
+            let [start, end, target] = catch.labels.map(|token| {
+                let TokenKind::Ident(name) = token.kind else {
+                    unreachable!();
+                };
+                match proc.labels.get(name) {
+                    Some(&index) => offsets[index] as u32,
+                    None => {
+                        self.error(catch.line, token.column, format!("unknown label `{name}`"));
+                        0
+                    }
+                }
+            });
+            handlers.push(Handler {
+                start,
+                end,
+                target,
+                slot: catch.slot,
+            });
+        }
+        let mut proc = Proc::named(proc.name, code);
+        proc.handlers = handlers;
+        proc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::value::Value;
+
+    use super::*;
+
+    /// Returns the diagnostics for `src` as `line:column: message`
+    fn diagnostics(src: &str) -> Vec<String> {
+        match assemble(src) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn unknown_mnemonic() {
+        assert_eq!(
+            diagnostics(".proc main\n    mvo 0, 1\n    ret\n.end\n"),
+            ["2:5: unknown mnemonic `mvo`"]
+        );
+    }
+
+    #[test]
+    fn wrong_operand_count() {
+        assert_eq!(
+            diagnostics(".proc main\n    alloc 1\n    movv 0\n    alloc 1, 2\n    ret\n.end\n"),
+            [
+                "3:5: `movv` expects 2 operand(s), found 1",
+                "4:5: `alloc` expects 1 operand(s), found 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined_label() {
+        assert_eq!(
+            diagnostics(".proc main\n    b nowhere\n    ret\n.end\n"),
+            ["2:7: unknown label `nowhere`"]
+        );
+        // Labels are local to their proc
+        let src = ".proc main\n    b done\n    ret\n.end\n.proc other\ndone:\n    ret\n.end\n";
+        assert_eq!(diagnostics(src), ["2:7: unknown label `done`"]);
+    }
+
+    #[test]
+    fn operand_overflow() {
+        let src = "
+.proc main
+    alloc 70000
+    alloc -1
+    movv 40000, 1
+    call 5000000000
+    movv 0, 99999999999999999999
+    ret
+.end
+";
+        assert_eq!(
+            diagnostics(src),
+            [
+                "3:11: operand `size` of `alloc` out of range for u16: 70000",
+                "4:11: operand `size` of `alloc` out of range for u16: -1",
+                "5:10: operand `dst` of `movv` out of range for i16: 40000",
+                "6:10: operand `proc` of `call` out of range for u32: 5000000000",
+                "7:13: operand `value` of `movv` out of range for i64: 99999999999999999999",
+            ]
+        );
+    }
+
+    #[test]
+    fn doc_example_runs() {
+        let module = assemble(include_str!("../examples/countdown.sasm")).unwrap();
+        assert_eq!(module.constants.len(), 3);
+        assert_eq!(module.constants[2], Constant::Str("hi\n".into()));
+        let entry = module.entry.unwrap();
+        let mut runtime = module.into_runtime().unwrap();
+        runtime.call(entry).unwrap();
+        runtime.run().unwrap();
+        let countdown = runtime.find_proc("countdown").unwrap();
+        assert_eq!(
+            runtime.invoke(countdown, &[Value::S64(3)]),
+            Ok(vec![Value::S64(0)])
+        );
+    }
+}
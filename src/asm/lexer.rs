@@ -0,0 +1,205 @@
+//! Splits a single line of assembly into tokens.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind<'a> {
+    /// Mnemonics, labels, procs and constants
+    Ident(&'a str),
+    /// An identifier prefixed with `.`
+    Directive(&'a str),
+    Int(i128),
+    Float(f64),
+    /// A string literal without its quotes, escapes are kept as written
+    Str(&'a str),
+    Comma,
+    Colon,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    /// 1-based column of the first character
+    pub column: usize,
+}
+
+/// An error at the given 1-based column
+pub struct LexError {
+    pub column: usize,
+    pub message: String,
+}
+
+/// Tokenizes `line`, stopping at the first `;`
+pub fn tokenize(line: &str) -> Result<Vec<Token<'_>>, LexError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    let column = |start: usize| line[..start].chars().count() + 1;
+    while let Some(&(start, c)) = chars.peek() {
+        let kind = match c {
+            ';' => break,
+            _ if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            ',' => {
+                chars.next();
+                TokenKind::Comma
+            }
+            ':' => {
+                chars.next();
+                TokenKind::Colon
+            }
+            '.' => {
+                chars.next();
+                let end = take_while(&mut chars, line.len(), is_ident_char);
+                if end == start + 1 {
+                    return Err(LexError {
+                        column: column(start),
+                        message: "expected directive name after `.`".into(),
+                    });
+                }
+                TokenKind::Directive(&line[start + 1..end])
+            }
+            '"' => {
+                chars.next();
+                let mut escaped = false;
+                let end = take_while(&mut chars, line.len(), |c| {
+                    let end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    !end
+                });
+                if chars.next().is_none() {
+                    return Err(LexError {
+                        column: column(start),
+                        message: "unterminated string".into(),
+                    });
+                }
+                TokenKind::Str(&line[start + 1..end])
+            }
+            _ if is_ident_start(c) => {
+                let end = take_while(&mut chars, line.len(), is_ident_char);
+                TokenKind::Ident(&line[start..end])
+            }
+            _ if c.is_ascii_digit() || c == '-' || c == '+' => {
+                chars.next();
+                let mut prev = c;
+                let end = take_while(&mut chars, line.len(), |c| {
+                    // Allow exponent signs like in `1e-3`
+                    let exponent_sign = matches!(c, '-' | '+') && matches!(prev, 'e' | 'E');
+                    prev = c;
+                    c.is_ascii_alphanumeric() || c == '_' || c == '.' || exponent_sign
+                });
+                let text = &line[start..end];
+                match parse_number(text) {
+                    Some(kind) => kind,
+                    None => {
+                        return Err(LexError {
+                            column: column(start),
+                            message: format!("invalid number `{text}`"),
+                        })
+                    }
+                }
+            }
+            _ => {
+                return Err(LexError {
+                    column: column(start),
+                    message: format!("unexpected character `{c}`"),
+                })
+            }
+        };
+        tokens.push(Token {
+            kind,
+            column: column(start),
+        });
+    }
+    Ok(tokens)
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Advances `chars` while `f` matches and returns the end offset
+fn take_while(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    len: usize,
+    mut f: impl FnMut(char) -> bool,
+) -> usize {
+    while let Some(&(index, c)) = chars.peek() {
+        if !f(c) {
+            return index;
+        }
+        chars.next();
+    }
+    len
+}
+
+/// Replaces the escapes of a string literal
+///
+/// Supports `\n`, `\r`, `\t`, `\0`, `\\`, `\"` and `\u{...}`.
+pub fn unescape(raw: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('u') => {
+                let rest = chars.as_str();
+                let code = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .and_then(|(hex, _)| u32::from_str_radix(hex, 16).ok().map(|code| (hex, code)));
+                let Some((hex, code)) = code else {
+                    return Err("expected `\\u{...}` with a hex code point".into());
+                };
+                let Some(c) = char::from_u32(code) else {
+                    return Err(format!("invalid code point `{hex}`"));
+                };
+                chars = rest[hex.len() + 2..].chars();
+                c
+            }
+            Some(c) => return Err(format!("unknown escape `\\{c}`")),
+            None => return Err("unfinished escape".into()),
+        });
+    }
+    Ok(out)
+}
+
+/// Parses decimal, `0x`, `0o` and `0b` integers and decimal floats
+fn parse_number(text: &str) -> Option<TokenKind<'static>> {
+    let (negative, digits) = match text.as_bytes()[0] {
+        b'-' => (true, &text[1..]),
+        b'+' => (false, &text[1..]),
+        _ => (false, text),
+    };
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits = digits.replace('_', "");
+    let (radix, body) = match digits.get(..2) {
+        Some("0x") => (16, &digits[2..]),
+        Some("0o") => (8, &digits[2..]),
+        Some("0b") => (2, &digits[2..]),
+        _ => (10, &digits[..]),
+    };
+    if !body.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    if radix == 10 && body.contains(['.', 'e', 'E']) {
+        let value: f64 = body.parse().ok()?;
+        return Some(TokenKind::Float(if negative { -value } else { value }));
+    }
+    let value = i128::from_str_radix(body, radix).ok()?;
+    Some(TokenKind::Int(if negative { -value } else { value }))
+}
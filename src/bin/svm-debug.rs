@@ -0,0 +1,49 @@
+//! Opens a module or assembly file in the debugger.
+//!
+//! ```text
+//! svm-debug <program>
+//! ```
+
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+
+use std::{env, process::ExitCode};
+
+use simple_vm::{
+    module::Module,
+    runtime::debug::{app::DebugApp, Debugger},
+};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [path] = &args[..] else {
+        eprintln!("usage: svm-debug <program>");
+        return ExitCode::FAILURE;
+    };
+    let module = match Module::read_file(path) {
+        Ok(module) => module,
+        Err(err) => {
+            eprintln!("{path}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(entry) = module.entry else {
+        eprintln!("{path}: no entry proc");
+        return ExitCode::FAILURE;
+    };
+    let runtime = match module.into_runtime() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("{path}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let debugger = match Debugger::new(runtime, entry) {
+        Ok(debugger) => debugger,
+        Err(trap) => {
+            eprintln!("trap: {trap}");
+            return ExitCode::FAILURE;
+        }
+    };
+    DebugApp::run(debugger);
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,90 @@
+//! Runs a module or assembly file.
+//!
+//! ```text
+//! svm run [--memory <bytes>] <program> [args...]
+//! ```
+//!
+//! The program is loaded as a module if it starts with the module magic and assembled
+//! otherwise. The integer `args` are passed to the entry proc in slots `-1`, `-2`, ...
+//!
+//! `--memory` sets the size of the linear memory, which is empty by default.
+//!
+//! ## Exit codes
+//!
+//! - `0`: the entry proc returned or the program halted
+//! - `1`: the program raised a trap
+//! - `2`: bad usage or the program could not be loaded
+
+use std::{env, process::ExitCode};
+
+use simple_vm::{
+    module::Module,
+    runtime::{builder::RuntimeBuilder, trap::TrapKind},
+    value,
+    value::Value,
+};
+
+const USAGE: &str = "usage: svm run [--memory <bytes>] <program> [args...]";
+
+const EXIT_TRAP: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+fn main() -> ExitCode {
+    match run(env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), u8> {
+    let (memory_size, args) = match &args[..] {
+        [command, option, size, args @ ..] if command == "run" && option == "--memory" => {
+            let size = size.parse::<usize>().map_err(|err| {
+                eprintln!("error: invalid memory size `{size}`: {err}");
+                EXIT_USAGE
+            })?;
+            (size, args)
+        }
+        [command, args @ ..] if command == "run" => (0, args),
+        _ => {
+            eprintln!("{USAGE}");
+            return Err(EXIT_USAGE);
+        }
+    };
+    let [path, args @ ..] = args else {
+        eprintln!("{USAGE}");
+        return Err(EXIT_USAGE);
+    };
+    let args = args
+        .iter()
+        .map(|arg| match arg.parse::<i64>() {
+            Ok(arg) => Ok(value!(@s64 arg)),
+            Err(err) => {
+                eprintln!("error: invalid argument `{arg}`: {err}");
+                Err(EXIT_USAGE)
+            }
+        })
+        .collect::<Result<Vec<Value>, u8>>()?;
+    let module = Module::read_file(path).map_err(|err| {
+        eprintln!("{path}: {err}");
+        EXIT_USAGE
+    })?;
+    let Some(entry) = module.entry else {
+        eprintln!("{path}: no entry proc");
+        return Err(EXIT_USAGE);
+    };
+    let mut runtime = module
+        .into_runtime_with(RuntimeBuilder::new().memory_size(memory_size))
+        .map_err(|err| {
+            eprintln!("{path}: {err}");
+            EXIT_USAGE
+        })?;
+    match runtime.invoke(entry, &args) {
+        Ok(_) => Ok(()),
+        Err(trap) if trap.kind == TrapKind::Halted => Ok(()),
+        Err(trap) => {
+            eprintln!("trap: {trap}");
+            Err(EXIT_TRAP)
+        }
+    }
+}
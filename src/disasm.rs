@@ -0,0 +1,305 @@
+//! Turns proc bytecode back into readable assembly.
+//!
+//! ```text
+//! .proc factorial               ; #1
+//!     0000  alloc 2
+//!     0003  bnz -1, L0016
+//!     000a  movv -1, 1
+//!     0015  ret
+//! L0016:
+//!     0016  movv 0, 1
+//!     ...
+//!     002d  call factorial
+//! .end
+//! ```
+//!
+//! Branch targets that land on an instruction are printed as labels. Unknown or truncated
+//! opcodes are flagged and end the listing of the proc.
+//!
+//! Exception handlers are printed as `.catch` directives after the header, with labels for
+//! offsets that land on an instruction or the end of the proc.
+
+use std::{collections::BTreeSet, fmt};
+
+use crate::{
+    module::Module,
+    opcodes::{decode_all, Listing, OperandKind, OperandType},
+    runtime::{proc::Proc, Constant, Runtime},
+};
+
+/// Names and values used to resolve operands
+#[derive(Default)]
+pub struct Symbols<'a> {
+    /// The proc table
+    pub procs: Vec<&'a Proc>,
+    pub constants: &'a [Constant],
+    /// Names of the host functions
+    pub hosts: Vec<&'a str>,
+}
+
+impl<'a> Symbols<'a> {
+    pub fn from_module(module: &'a Module) -> Self {
+        Self {
+            procs: module.procs.iter().collect(),
+            constants: &module.constants,
+            hosts: module.hosts.iter().map(|name| &**name).collect(),
+        }
+    }
+
+    pub fn from_runtime(runtime: &'a Runtime) -> Self {
+        Self {
+            procs: runtime.procs().collect(),
+            constants: runtime.constants(),
+            hosts: runtime.host_names().collect(),
+        }
+    }
+
+    fn proc_name(&self, index: i64) -> Option<&'a str> {
+        self.procs.get(index as usize)?.name.as_deref()
+    }
+}
+
+/// Disassembles all procs of `module`
+pub fn disassemble_module(module: &Module) -> String {
+    let symbols = Symbols::from_module(module);
+    let mut out = String::new();
+    for (index, proc) in module.procs.iter().enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        disassemble(&mut out, proc, Some(index as u32), &symbols).unwrap();
+    }
+    out
+}
+
+/// Writes the listing of `proc`, whose index in the proc table is `index` if known
+pub fn disassemble(
+    out: &mut impl fmt::Write,
+    proc: &Proc,
+    index: Option<u32>,
+    symbols: &Symbols,
+) -> fmt::Result {
+    let Listing { insns, error } = decode_all(&proc.code);
+    let mut starts: BTreeSet<usize> = insns.iter().map(|(offset, _)| *offset).collect();
+    if error.is_none() {
+        starts.insert(proc.code.len());
+    }
+    let handler_offsets = proc
+        .handlers
+        .iter()
+        .flat_map(|handler| [handler.start, handler.end, handler.target])
+        .map(|offset| offset as usize);
+    let targets: BTreeSet<usize> = insns
+        .iter()
+        .filter_map(|(offset, insn)| usize::try_from(insn.branch_target(*offset)?).ok())
+        .chain(handler_offsets)
+        .filter(|target| starts.contains(target))
+        .collect();
+    let label = |offset: u32| match targets.contains(&(offset as usize)) {
+        true => format!("L{offset:04x}"),
+        false => offset.to_string(),
+    };
+
+    let header = format!(".proc {}", proc.name.as_deref().unwrap_or("?"));
+    match index {
+        Some(index) => writeln!(out, "{header:<29} ; #{index}")?,
+        None => writeln!(out, "{header}")?,
+    }
+    for handler in &proc.handlers {
+        writeln!(
+            out,
+            "    .catch {}, {}, {}, {}",
+            label(handler.start),
+            label(handler.end),
+            label(handler.target),
+            handler.slot
+        )?;
+    }
+    for (offset, insn) in &insns {
+        if targets.contains(offset) {
+            writeln!(out, "L{offset:04x}:")?;
+        }
+        let mut line = format!("    {offset:04x}  {}", insn.info.mnemonic);
+        let mut comment = None;
+        for (i, (operand, &value)) in insn.info.operands.iter().zip(&insn.operands).enumerate() {
+            line.push_str(if i == 0 { " " } else { ", " });
+            match operand.kind {
+                OperandKind::Offset => {
+                    let target = insn.branch_target(*offset).unwrap();
+                    match usize::try_from(target) {
+                        Ok(target) if targets.contains(&target) => {
+                            line.push_str(&format!("L{target:04x}"));
+                        }
+                        _ => {
+                            line.push_str(&value.to_string());
+                            comment = Some(format!("invalid target {target}"));
+                        }
+                    }
+                }
+                OperandKind::Proc => match symbols.proc_name(value) {
+                    Some(name) => line.push_str(name),
+                    None => {
+                        line.push_str(&value.to_string());
+                        if value as usize >= symbols.procs.len() {
+                            comment = Some("unknown proc".into());
+                        }
+                    }
+                },
+                OperandKind::Constant => {
+                    line.push_str(&value.to_string());
+                    comment = Some(match symbols.constants.get(value as usize) {
+                        Some(Constant::S64(value)) => format!("{value}"),
+                        Some(Constant::F64(value)) => format!("{value:?}"),
+                        Some(Constant::Str(value)) => format!("{value:?}"),
+                        None => "unknown constant".into(),
+                    });
+                }
+                OperandKind::Host => match symbols.hosts.get(value as usize) {
+                    Some(name) => line.push_str(name),
+                    None => {
+                        line.push_str(&value.to_string());
+                        comment = Some("unknown host function".into());
+                    }
+                },
+                OperandKind::Immediate if operand.ty == OperandType::F64 => {
+                    line.push_str(&format!("{:?}", f64::from_bits(value as u64)));
+                }
+                OperandKind::Slot | OperandKind::Immediate => line.push_str(&value.to_string()),
+            }
+        }
+        match comment {
+            Some(comment) => writeln!(out, "{line:<29} ; {comment}")?,
+            None => writeln!(out, "{line}")?,
+        }
+    }
+    if let Some((offset, error)) = error {
+        let line = format!("    {offset:04x}  .byte 0x{:02x}", proc.code[offset]);
+        writeln!(out, "{line:<29} ; {error}")?;
+    } else if targets.contains(&proc.code.len()) {
+        writeln!(out, "L{:04x}:", proc.code.len())?;
+    }
+    writeln!(out, ".end")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::assemble,
+        opcodes::{BRANCH, CALL, CALL_HOST, LOAD_CONST, MOVE_VALUE, RETURN},
+    };
+
+    use super::*;
+
+    /// Disassembles `code` without symbols
+    fn listing(code: Vec<u8>) -> String {
+        let mut out = String::new();
+        disassemble(&mut out, &Proc::new(code), None, &Symbols::default()).unwrap();
+        out
+    }
+
+    #[test]
+    fn module() {
+        let src = r#"
+.const limit 10
+.const pi 3.5
+.const greeting "hi\n"
+
+.proc main
+    alloc 2
+    .catch try, done, failed, 1
+try:
+    ldc 0, limit
+    ldc 0, pi
+    ldc 0, greeting
+    ldp 0, count
+    hcall log
+    call count
+done:
+    ret
+failed:
+    print_str 1
+    ret
+.end
+
+.proc count
+loop:
+    subsi -1, -1, 1
+    bnz -1, loop
+    movf -1, 0.5
+    ret
+.end
+"#;
+        let expected = r#".proc main                    ; #0
+    .catch L0003, L0029, L002a, 1
+    0000  alloc 2
+L0003:
+    0003  ldc 0, 0            ; 10
+    000a  ldc 0, 1            ; 3.5
+    0011  ldc 0, 2            ; "hi\n"
+    0018  ldp 0, count
+    001f  hcall log
+    0024  call count
+L0029:
+    0029  ret
+L002a:
+    002a  print_str 1
+    002d  ret
+.end
+
+.proc count                   ; #1
+L0000:
+    0000  subsi -1, -1, 1
+    0009  bnz -1, L0000
+    0010  movf -1, 0.5
+    001b  ret
+.end
+"#;
+        assert_eq!(disassemble_module(&assemble(src).unwrap()), expected);
+    }
+
+    #[test]
+    fn invalid_code() {
+        let mut code = vec![BRANCH];
+        code.extend(100i32.to_ne_bytes());
+        code.push(0xff);
+        assert_eq!(
+            listing(code),
+            "\
+.proc ?
+    0000  b 100               ; invalid target 105
+    0005  .byte 0xff          ; unknown opcode [0xff]
+.end
+"
+        );
+        assert_eq!(
+            listing(vec![RETURN, MOVE_VALUE, 0]),
+            "\
+.proc ?
+    0000  ret
+    0001  .byte 0x02          ; truncated `movv` instruction
+.end
+"
+        );
+    }
+
+    #[test]
+    fn unknown_symbols() {
+        let mut code = vec![CALL];
+        code.extend(7u32.to_ne_bytes());
+        code.push(LOAD_CONST);
+        code.extend(0i16.to_ne_bytes());
+        code.extend(1u32.to_ne_bytes());
+        code.push(CALL_HOST);
+        code.extend(2u32.to_ne_bytes());
+        assert_eq!(
+            listing(code),
+            "\
+.proc ?
+    0000  call 7              ; unknown proc
+    0005  ldc 0, 1            ; unknown constant
+    000c  hcall 2             ; unknown host function
+.end
+"
+        );
+    }
+}
@@ -0,0 +1,16 @@
+//! A simple register-less virtual machine operating on stack slots.
+//!
+//! Programs are written in assembly ([asm]) or with the [make_runtime] macro, stored as
+//! [module]s and executed by a [Runtime](runtime::Runtime).
+
+// The opcode table is expanded one opcode per recursion step
+#![recursion_limit = "256"]
+
+pub mod asm;
+pub mod disasm;
+pub mod module;
+pub mod opcodes;
+pub mod runtime;
+pub mod util;
+pub mod value;
+pub mod verify;
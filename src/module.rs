@@ -0,0 +1,110 @@
+//! A program that is not yet loaded into a [Runtime].
+
+pub mod format;
+
+use std::{fmt, fs, io, path::Path};
+
+use crate::{
+    asm::{self, Diagnostic},
+    runtime::{builder::RuntimeBuilder, proc::Proc, Constant, Runtime},
+    verify::VerifyError,
+};
+
+use self::format::LoadError;
+
+#[derive(Default)]
+pub struct Module {
+    pub constants: Vec<Constant>,
+    pub procs: Vec<Proc>,
+    /// Names of the host functions called by the module
+    ///
+    /// Functions for them are registered with [Runtime::register_host].
+    pub hosts: Vec<String>,
+    /// The proc to call when running the module
+    pub entry: Option<u32>,
+}
+
+impl Module {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes a module from the [format] used by [Module::to_bytes]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LoadError> {
+        format::read(bytes)
+    }
+
+    /// Reads a module file or, if it does not start with [format::MAGIC], assembles it
+    pub fn read_file(path: impl AsRef<Path>) -> Result<Self, ReadError> {
+        let bytes = fs::read(path).map_err(ReadError::Io)?;
+        if bytes.starts_with(&format::MAGIC) {
+            return Self::from_bytes(&bytes).map_err(ReadError::Load);
+        }
+        let src = String::from_utf8(bytes)
+            .map_err(|err| ReadError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+        asm::assemble(&src).map_err(ReadError::Assemble)
+    }
+
+    /// Encodes the module in the module file [format]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        format::write(&mut out, self);
+        out
+    }
+
+    /// Returns the index of the proc called `name`
+    pub fn find_proc(&self, name: &str) -> Option<u32> {
+        self.procs
+            .iter()
+            .position(|proc| proc.name.as_deref() == Some(name))
+            .map(|index| index as u32)
+    }
+
+    /// Verifies the module and creates a new [Runtime] containing all its constants and procs
+    pub fn into_runtime(self) -> Result<Runtime, VerifyError> {
+        self.into_runtime_with(RuntimeBuilder::new())
+    }
+
+    /// Like [Module::into_runtime], but configures the runtime with `builder`
+    pub fn into_runtime_with(self, builder: RuntimeBuilder) -> Result<Runtime, VerifyError> {
+        let mut runtime = builder.build();
+        for constant in self.constants {
+            runtime.push_constant(constant);
+        }
+        for proc in self.procs {
+            runtime.push_proc(proc);
+        }
+        for host in self.hosts {
+            runtime.push_host(host);
+        }
+        runtime.verify()?;
+        Ok(runtime)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    Load(LoadError),
+    Assemble(Vec<Diagnostic>),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(err) => write!(f, "{err}"),
+            ReadError::Load(err) => write!(f, "{err}"),
+            ReadError::Assemble(diagnostics) => {
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{diagnostic}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
@@ -0,0 +1,461 @@
+//! ## Module file format
+//!
+//! All integers are native-endian like the bytecode they contain, so modules are only portable
+//! between hosts of the same byte order.
+//!
+//! ```text
+//! +==========================================+
+//! | Magic `\0svm`                   [u8; 4]  |
+//! | Version                         u16      |
+//! +==========================================+
+//! | Constant count                  u32      |
+//! +------------------------------------------+
+//! | Tag (0 = s64, 1 = f64, 2 = str) u8       |  for each constant
+//! | Value                           [u8; 8]  |  s64 and f64
+//! | Length                          u32      |  str
+//! | UTF-8 text                      [u8]     |  str
+//! +==========================================+
+//! | Proc count                      u32      |
+//! +------------------------------------------+
+//! | Code length                     u32      |  for each proc
+//! | Code                            [u8]     |
+//! +==========================================+
+//! | Section id                      u8       |  optional sections,
+//! | Section length                  u32      |  terminated by id 0
+//! | Section data                    [u8]     |
+//! +==========================================+
+//! ```
+//!
+//! ## Sections
+//!
+//! - [SECTION_ENTRY]: the `u32` index of the entry proc
+//! - [SECTION_NAMES]: for each proc a `u32` length followed by its UTF-8 name, empty if unnamed
+//! - [SECTION_HOSTS]: a `u32` count followed by the `u32` length and UTF-8 name of each host
+//!   function
+//! - [SECTION_HANDLERS]: for each proc a `u32` count followed by the `u32` start, end and
+//!   target offsets and the `i16` slot of each exception handler
+//!
+//! Unknown sections are skipped.
+//!
+//! The version changes only when the encoding of existing contents changes. New opcodes are
+//! appended to the opcode table and new constant tags and sections extend the format, so
+//! older modules keep loading.
+
+use std::{fmt, str};
+
+use crate::{
+    runtime::{
+        proc::{Handler, Proc},
+        Constant,
+    },
+    util::Write,
+    verify::VerifyError,
+};
+
+use super::Module;
+
+pub const MAGIC: [u8; 4] = *b"\0svm";
+pub const VERSION: u16 = 2;
+
+pub const SECTION_END: u8 = 0;
+pub const SECTION_ENTRY: u8 = 1;
+pub const SECTION_NAMES: u8 = 2;
+pub const SECTION_HOSTS: u8 = 3;
+pub const SECTION_HANDLERS: u8 = 4;
+
+const CONSTANT_S64: u8 = 0;
+const CONSTANT_F64: u8 = 1;
+const CONSTANT_STR: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// The file does not start with [MAGIC]
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The file ends in the middle of an item
+    UnexpectedEof,
+    InvalidConstantTag(u8),
+    DuplicateSection(u8),
+    /// A section is longer or shorter than its contents
+    BadSectionLength(u8),
+    /// A proc or host function name is not valid UTF-8
+    InvalidName,
+    /// A string constant is not valid UTF-8
+    InvalidString,
+    /// The entry index is not in the proc table
+    InvalidEntry(u32),
+    TrailingBytes,
+    /// The module is well-formed but fails verification
+    Verify(VerifyError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::BadMagic => write!(f, "not a module file"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported module version {version}, expected {VERSION}"
+                )
+            }
+            LoadError::UnexpectedEof => write!(f, "unexpected end of file"),
+            LoadError::InvalidConstantTag(tag) => write!(f, "invalid constant tag {tag}"),
+            LoadError::DuplicateSection(id) => write!(f, "duplicate section {id}"),
+            LoadError::BadSectionLength(id) => write!(f, "bad length of section {id}"),
+            LoadError::InvalidName => write!(f, "name is not valid UTF-8"),
+            LoadError::InvalidString => write!(f, "string constant is not valid UTF-8"),
+            LoadError::InvalidEntry(index) => write!(f, "entry proc #{index} does not exist"),
+            LoadError::TrailingBytes => write!(f, "trailing bytes after the last section"),
+            LoadError::Verify(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<VerifyError> for LoadError {
+    fn from(value: VerifyError) -> Self {
+        Self::Verify(value)
+    }
+}
+
+/// Encodes `module` in the module file format
+pub fn write<T: Write>(out: &mut T, module: &Module) {
+    out.write(&MAGIC);
+    out.write_u16(VERSION);
+    out.write_u32(module.constants.len() as u32);
+    for constant in &module.constants {
+        match constant {
+            Constant::S64(value) => {
+                out.write_u8(CONSTANT_S64);
+                out.write_i64(*value);
+            }
+            Constant::F64(value) => {
+                out.write_u8(CONSTANT_F64);
+                out.write_u64(value.to_bits());
+            }
+            Constant::Str(value) => {
+                out.write_u8(CONSTANT_STR);
+                out.write_u32(value.len() as u32);
+                out.write(value.as_bytes());
+            }
+        }
+    }
+    out.write_u32(module.procs.len() as u32);
+    for proc in &module.procs {
+        out.write_u32(proc.code.len() as u32);
+        out.write(&proc.code);
+    }
+    if let Some(entry) = module.entry {
+        let mut section = Vec::new();
+        section.write_u32(entry);
+        write_section(out, SECTION_ENTRY, &section);
+    }
+    if module.procs.iter().any(|proc| proc.name.is_some()) {
+        let mut section = Vec::new();
+        for proc in &module.procs {
+            let name = proc.name.as_deref().unwrap_or("");
+            section.write_u32(name.len() as u32);
+            section.write(name.as_bytes());
+        }
+        write_section(out, SECTION_NAMES, &section);
+    }
+    if !module.hosts.is_empty() {
+        let mut section = Vec::new();
+        section.write_u32(module.hosts.len() as u32);
+        for name in &module.hosts {
+            section.write_u32(name.len() as u32);
+            section.write(name.as_bytes());
+        }
+        write_section(out, SECTION_HOSTS, &section);
+    }
+    if module.procs.iter().any(|proc| !proc.handlers.is_empty()) {
+        let mut section = Vec::new();
+        for proc in &module.procs {
+            section.write_u32(proc.handlers.len() as u32);
+            for handler in &proc.handlers {
+                section.write_u32(handler.start);
+                section.write_u32(handler.end);
+                section.write_u32(handler.target);
+                section.write_i16(handler.slot);
+            }
+        }
+        write_section(out, SECTION_HANDLERS, &section);
+    }
+    out.write_u8(SECTION_END);
+}
+
+fn write_section<T: Write>(out: &mut T, id: u8, data: &[u8]) {
+    out.write_u8(id);
+    out.write_u32(data.len() as u32);
+    out.write(data);
+}
+
+/// Decodes a module from the module file format
+pub fn read(bytes: &[u8]) -> Result<Module, LoadError> {
+    let mut src = Reader { bytes };
+    if src.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(LoadError::BadMagic);
+    }
+    let version = src.u16()?;
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+    let mut module = Module::new();
+    let constants = src.u32()?;
+    for _ in 0..constants {
+        let tag = src.u8()?;
+        module.constants.push(match tag {
+            CONSTANT_S64 => Constant::S64(src.u64()? as i64),
+            CONSTANT_F64 => Constant::F64(f64::from_bits(src.u64()?)),
+            CONSTANT_STR => {
+                let len = src.u32()? as usize;
+                let bytes = src.bytes(len)?;
+                let value = str::from_utf8(bytes).map_err(|_| LoadError::InvalidString)?;
+                Constant::Str(value.to_owned())
+            }
+            _ => return Err(LoadError::InvalidConstantTag(tag)),
+        });
+    }
+    let procs = src.u32()?;
+    for _ in 0..procs {
+        let len = src.u32()? as usize;
+        module.procs.push(Proc::new(src.bytes(len)?));
+    }
+    let mut seen = Vec::new();
+    loop {
+        let id = src.u8()?;
+        if id == SECTION_END {
+            break;
+        }
+        if seen.contains(&id) {
+            return Err(LoadError::DuplicateSection(id));
+        }
+        seen.push(id);
+        let len = src.u32()? as usize;
+        let mut section = Reader {
+            bytes: src.bytes(len)?,
+        };
+        match id {
+            SECTION_ENTRY => {
+                let entry = section.u32()?;
+                if entry >= procs {
+                    return Err(LoadError::InvalidEntry(entry));
+                }
+                module.entry = Some(entry);
+            }
+            SECTION_NAMES => {
+                for proc in &mut module.procs {
+                    let name = section.str()?;
+                    if !name.is_empty() {
+                        proc.name = Some(name.to_owned());
+                    }
+                }
+            }
+            SECTION_HOSTS => {
+                let hosts = section.u32()?;
+                for _ in 0..hosts {
+                    module.hosts.push(section.str()?.to_owned());
+                }
+            }
+            SECTION_HANDLERS => {
+                for proc in &mut module.procs {
+                    let handlers = section.u32()?;
+                    for _ in 0..handlers {
+                        proc.handlers.push(Handler {
+                            start: section.u32()?,
+                            end: section.u32()?,
+                            target: section.u32()?,
+                            slot: section.u16()? as i16,
+                        });
+                    }
+                }
+            }
+            _ => continue,
+        }
+        if !section.bytes.is_empty() {
+            return Err(LoadError::BadSectionLength(id));
+        }
+    }
+    if !src.bytes.is_empty() {
+        return Err(LoadError::TrailingBytes);
+    }
+    Ok(module)
+}
+
+/// Bounds-checked reader over a byte slice
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        if self.bytes.len() < len {
+            return Err(LoadError::UnexpectedEof);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_ne_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_ne_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        Ok(u64::from_ne_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a `u32` length followed by UTF-8
+    fn str(&mut self) -> Result<&'a str, LoadError> {
+        let len = self.u32()? as usize;
+        str::from_utf8(self.bytes(len)?).map_err(|_| LoadError::InvalidName)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+
+    use super::*;
+
+    /// An empty module up to the sections
+    fn empty() -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.write_u16(VERSION);
+        out.write_u32(0);
+        out.write_u32(0);
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = r#"
+.const one 1
+.const half 0.5
+.const hello "hi\n"
+.entry start
+
+.proc helper
+    ret
+.end
+
+.proc start
+    alloc 1
+    .catch try, done, failed, 0
+try:
+    hcall print
+    call helper
+done:
+    hlt
+failed:
+    hlt
+.end
+"#;
+        let module = assemble(src).unwrap();
+        let read = Module::from_bytes(&module.to_bytes()).unwrap();
+        assert_eq!(read.constants, module.constants);
+        assert_eq!(read.hosts, ["print"]);
+        assert_eq!(read.entry, Some(1));
+        assert_eq!(read.procs.len(), 2);
+        for (read, proc) in read.procs.iter().zip(&module.procs) {
+            assert_eq!(read.name, proc.name);
+            assert_eq!(read.code, proc.code);
+            assert_eq!(read.handlers, proc.handlers);
+        }
+        assert_eq!(read.procs[1].handlers.len(), 1);
+        assert_eq!(read.to_bytes(), module.to_bytes());
+    }
+
+    #[test]
+    fn empty_module() {
+        let mut bytes = empty();
+        bytes.write_u8(SECTION_END);
+        let module = read(&bytes).unwrap();
+        assert!(module.constants.is_empty() && module.procs.is_empty());
+        assert_eq!(module.entry, None);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut bytes = empty();
+        bytes.write_u8(SECTION_END);
+        bytes[1] = b'x';
+        assert_eq!(read(&bytes).err(), Some(LoadError::BadMagic));
+        assert_eq!(read(b"\0sv").err(), Some(LoadError::BadMagic));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes = empty();
+        bytes.write_u8(SECTION_END);
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_ne_bytes());
+        assert_eq!(
+            read(&bytes).err(),
+            Some(LoadError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        let module = assemble(".const one 1\n.proc main\n    hlt\n.end\n").unwrap();
+        let bytes = module.to_bytes();
+        assert!(read(&bytes).is_ok());
+        for len in MAGIC.len()..bytes.len() {
+            assert_eq!(
+                read(&bytes[..len]).err(),
+                Some(LoadError::UnexpectedEof),
+                "truncated to {len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_section() {
+        let mut bytes = empty();
+        write_section(&mut bytes, SECTION_NAMES, &[]);
+        write_section(&mut bytes, SECTION_NAMES, &[]);
+        bytes.write_u8(SECTION_END);
+        assert_eq!(
+            read(&bytes).err(),
+            Some(LoadError::DuplicateSection(SECTION_NAMES))
+        );
+    }
+
+    #[test]
+    fn bad_section_length() {
+        let mut bytes = empty();
+        // A host count of 0 followed by an extra byte
+        write_section(&mut bytes, SECTION_HOSTS, &[0, 0, 0, 0, 0]);
+        bytes.write_u8(SECTION_END);
+        assert_eq!(
+            read(&bytes).err(),
+            Some(LoadError::BadSectionLength(SECTION_HOSTS))
+        );
+    }
+
+    #[test]
+    fn invalid_entry() {
+        let mut bytes = empty();
+        write_section(&mut bytes, SECTION_ENTRY, &1u32.to_ne_bytes());
+        bytes.write_u8(SECTION_END);
+        assert_eq!(read(&bytes).err(), Some(LoadError::InvalidEntry(1)));
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut bytes = empty();
+        bytes.write_u8(SECTION_END);
+        bytes.write_u8(0);
+        assert_eq!(read(&bytes).err(), Some(LoadError::TrailingBytes));
+    }
+}
@@ -1,7 +1,14 @@
 //! This module contains all opcodes supported by the runtime.
 
+use std::fmt;
+
 use crate::util::{Read, Write};
 
+// Opcodes are numbered by their position, so new ones are only ever appended to keep the
+// encoding of existing modules.
+//
+// Operands are `name: type`, with `as Kind` giving the [OperandKind] of operands that
+// aren't slots or immediates
 #[rustfmt::skip]
 macro_rules! opcodes {
     ($path: path) => {
@@ -10,21 +17,23 @@ macro_rules! opcodes {
             (alloc,        alloc,      { size: u16 })
             (r#move,       mov,        { dst: i16, src: i16 })
             (move_value,   movv,       { dst: i16, value: i64 })
-            (load_const,   ldc,        { dst: i16, index: u32 })
-            (load_proc,    ldp,        { dst: i16, index: u32 })
+            (load_const,   ldc,        { dst: i16, constant: u32 as Constant })
+            (load_proc,    ldp,        { dst: i16, proc: u32 as Proc })
             // Jumps
-            (call,         call,       { index: u32 })
+            (call,         call,       { proc: u32 as Proc })
+            // Calls a proc or closure
             (call_dynamic, call_dyn,   { src: i16 })
-            (branch,       b,          { offset: i32 })
-            (branch_z,     bz,         { src: i16, offset: i32 })
-            (branch_nz,    bnz,        { src: i16, offset: i32 })
-            (branch_lz,    blz,        { src: i16, offset: i32 })
-            (branch_lez,   blez,       { src: i16, offset: i32 })
-            (branch_gz,    bgz,        { src: i16, offset: i32 })
-            (branch_gez,   bgez,       { src: i16, offset: i32 })
+            (call_host,    hcall,      { host: u32 as Host })
+            (branch,       b,          { offset: i32 as Offset })
+            (branch_z,     bz,         { src: i16, offset: i32 as Offset })
+            (branch_nz,    bnz,        { src: i16, offset: i32 as Offset })
+            (branch_lz,    blz,        { src: i16, offset: i32 as Offset })
+            (branch_lez,   blez,       { src: i16, offset: i32 as Offset })
+            (branch_gz,    bgz,        { src: i16, offset: i32 as Offset })
+            (branch_gez,   bgez,       { src: i16, offset: i32 as Offset })
             (r#return,     ret,        {})
             // Arithmetic
-            // s64
+            // s64, overflow and division by zero trap
             (add_s64,      adds,       { dst: i16, left: i16, right: i16 })
             (sub_s64,      subs,       { dst: i16, left: i16, right: i16 })
             (mul_s64,      muls,       { dst: i16, left: i16, right: i16 })
@@ -43,12 +52,172 @@ macro_rules! opcodes {
             // Special
             (halt,         hlt,        {})
             (breakpoint,   brkp,       {})
+            // Comparison
+            // s64
+            (eq_s64,       eq,         { dst: i16, left: i16, right: i16 })
+            (ne_s64,       ne,         { dst: i16, left: i16, right: i16 })
+            (lt_s64,       lt,         { dst: i16, left: i16, right: i16 })
+            (le_s64,       le,         { dst: i16, left: i16, right: i16 })
+            (gt_s64,       gt,         { dst: i16, left: i16, right: i16 })
+            (ge_s64,       ge,         { dst: i16, left: i16, right: i16 })
+            // u64
+            (lt_u64,       ltu,        { dst: i16, left: i16, right: i16 })
+            (le_u64,       leu,        { dst: i16, left: i16, right: i16 })
+            (gt_u64,       gtu,        { dst: i16, left: i16, right: i16 })
+            (ge_u64,       geu,        { dst: i16, left: i16, right: i16 })
+            (branch_eq,    beq,        { left: i16, right: i16, offset: i32 as Offset })
+            (branch_ne,    bne,        { left: i16, right: i16, offset: i32 as Offset })
+            (branch_lt,    blt,        { left: i16, right: i16, offset: i32 as Offset })
+            (branch_le,    ble,        { left: i16, right: i16, offset: i32 as Offset })
+            (branch_gt,    bgt,        { left: i16, right: i16, offset: i32 as Offset })
+            (branch_ge,    bge,        { left: i16, right: i16, offset: i32 as Offset })
+            (branch_ltu,   bltu,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_leu,   bleu,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_gtu,   bgtu,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_geu,   bgeu,       { left: i16, right: i16, offset: i32 as Offset })
+            // f64, false if an operand is NaN except for `nef` and `unof`
+            (eq_f64,       eqf,        { dst: i16, left: i16, right: i16 })
+            (ne_f64,       nef,        { dst: i16, left: i16, right: i16 })
+            (lt_f64,       ltf,        { dst: i16, left: i16, right: i16 })
+            (le_f64,       lef,        { dst: i16, left: i16, right: i16 })
+            (gt_f64,       gtf,        { dst: i16, left: i16, right: i16 })
+            (ge_f64,       gef,        { dst: i16, left: i16, right: i16 })
+            (uno_f64,      unof,       { dst: i16, left: i16, right: i16 })
+            (is_nan_f64,   nanf,       { dst: i16, src: i16 })
+            (branch_eqf,   beqf,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_nef,   bnef,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_ltf,   bltf,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_lef,   blef,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_gtf,   bgtf,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_gef,   bgef,       { left: i16, right: i16, offset: i32 as Offset })
+            (branch_unof,  bunof,      { left: i16, right: i16, offset: i32 as Offset })
+            (branch_nanf,  bnanf,      { src: i16, offset: i32 as Offset })
+            // Bitwise
+            // Shifts by 64 or more (as u64) shift out all bits, rotates are modulo 64
+            (and_s64,      and,        { dst: i16, left: i16, right: i16 })
+            (or_s64,       or,         { dst: i16, left: i16, right: i16 })
+            (xor_s64,      xor,        { dst: i16, left: i16, right: i16 })
+            (not_s64,      not,        { dst: i16, src: i16 })
+            (shl_s64,      shl,        { dst: i16, left: i16, right: i16 })
+            (shr_u64,      shr,        { dst: i16, left: i16, right: i16 })
+            (shr_s64,      sar,        { dst: i16, left: i16, right: i16 })
+            (rotl_s64,     rol,        { dst: i16, left: i16, right: i16 })
+            (rotr_s64,     ror,        { dst: i16, left: i16, right: i16 })
+            (popcnt_s64,   popcnt,     { dst: i16, src: i16 })
+            (clz_s64,      clz,        { dst: i16, src: i16 })
+            (ctz_s64,      ctz,        { dst: i16, src: i16 })
+            // Conversion
+            (move_f64,     movf,       { dst: i16, value: f64 })
+            (s64_to_f64,   itof,       { dst: i16, src: i16 })
+            // Out of range values wrap around, NaN and infinities become 0
+            (f64_to_s64,   ftoi,       { dst: i16, src: i16 })
+            // Out of range values saturate, NaN becomes 0
+            (f64_sat_s64,  ftoi_sat,   { dst: i16, src: i16 })
+            // Out of range values and NaN trap
+            (f64_chk_s64,  ftoi_chk,   { dst: i16, src: i16 })
+            (floor_f64,    floor,      { dst: i16, src: i16 })
+            (ceil_f64,     ceil,       { dst: i16, src: i16 })
+            // Rounds half-way cases away from zero
+            (round_f64,    round,      { dst: i16, src: i16 })
+            (trunc_f64,    trunc,      { dst: i16, src: i16 })
+            // Bit reinterpretation
+            (f64_to_bits,  fbits,      { dst: i16, src: i16 })
+            (bits_to_f64,  bitsf,      { dst: i16, src: i16 })
+            // Immediate operands
+            (branch_eqi,   beqi,       { src: i16, value: i32, offset: i32 as Offset })
+            (branch_nei,   bnei,       { src: i16, value: i32, offset: i32 as Offset })
+            (branch_lti,   blti,       { src: i16, value: i32, offset: i32 as Offset })
+            (branch_lei,   blei,       { src: i16, value: i32, offset: i32 as Offset })
+            (branch_gti,   bgti,       { src: i16, value: i32, offset: i32 as Offset })
+            (branch_gei,   bgei,       { src: i16, value: i32, offset: i32 as Offset })
+            // Overflow traps
+            (add_s64_imm,  addsi,      { dst: i16, src: i16, value: i32 })
+            (sub_s64_imm,  subsi,      { dst: i16, src: i16, value: i32 })
+            (mul_s64_imm,  mulsi,      { dst: i16, src: i16, value: i32 })
+            // Overflow wraps around
+            (add_s64_wrap, adds_wrap,  { dst: i16, left: i16, right: i16 })
+            (sub_s64_wrap, subs_wrap,  { dst: i16, left: i16, right: i16 })
+            (mul_s64_wrap, muls_wrap,  { dst: i16, left: i16, right: i16 })
+            // Overflow saturates
+            (add_s64_sat,  adds_sat,   { dst: i16, left: i16, right: i16 })
+            (sub_s64_sat,  subs_sat,   { dst: i16, left: i16, right: i16 })
+            (mul_s64_sat,  muls_sat,   { dst: i16, left: i16, right: i16 })
+            // High 64 bits of the 128-bit product
+            (mulh_s64,     mulh,       { dst: i16, left: i16, right: i16 })
+            // u64, division by zero traps
+            (div_u64,      divu,       { dst: i16, left: i16, right: i16 })
+            (rem_u64,      remu,       { dst: i16, left: i16, right: i16 })
+            (mulh_u64,     mulhu,      { dst: i16, left: i16, right: i16 })
+            // Sign and zero extension of the low bits
+            (sign_ext8,    sext8,      { dst: i16, src: i16 })
+            (sign_ext16,   sext16,     { dst: i16, src: i16 })
+            (sign_ext32,   sext32,     { dst: i16, src: i16 })
+            (zero_ext8,    zext8,      { dst: i16, src: i16 })
+            (zero_ext16,   zext16,     { dst: i16, src: i16 })
+            (zero_ext32,   zext32,     { dst: i16, src: i16 })
+            // Heap, fields start out as s64 0
+            (new_record,   newrec,     { dst: i16, size: u16 })
+            (new_array,    newarr,     { dst: i16, len: i16 })
+            (load_field,   ldfld,      { dst: i16, src: i16, field: u16 })
+            (store_field,  stfld,      { dst: i16, field: u16, src: i16 })
+            (load_elem,    ldelem,     { dst: i16, src: i16, index: i16 })
+            (store_elem,   stelem,     { dst: i16, index: i16, src: i16 })
+            (array_len,    len,        { dst: i16, src: i16 })
+            (print_ref,    print_ref,  { src: i16 })
+            // Strings, lengths and indices count bytes
+            (str_len,      slen,       { dst: i16, src: i16 })
+            (str_chars,    slenc,      { dst: i16, src: i16 })
+            (str_concat,   scat,       { dst: i16, left: i16, right: i16 })
+            // Indices must lie on char boundaries
+            (str_sub,      ssub,       { dst: i16, src: i16, start: i16, end: i16 })
+            // -1, 0 or 1 by comparing the bytes
+            (str_cmp,      scmp,       { dst: i16, left: i16, right: i16 })
+            (str_byte,     sbyte,      { dst: i16, src: i16, index: i16 })
+            // Code point of the char starting at the index
+            (str_char,     schar,      { dst: i16, src: i16, index: i16 })
+            (s64_to_str,   itos,       { dst: i16, src: i16 })
+            (f64_to_str,   ftos,       { dst: i16, src: i16 })
+            // Invalid numbers trap
+            (str_to_s64,   stoi,       { dst: i16, src: i16 })
+            (str_to_f64,   stof,       { dst: i16, src: i16 })
+            (print_str,    print_str,  { src: i16 })
+            // Slot `base + index` of the locals, both must be in the frame
+            (load_index,   ldx,        { dst: i16, base: i16, index: i16 })
+            (store_index,  stx,        { base: i16, index: i16, src: i16 })
+            // Ranges of `len` slots, which may overlap
+            (copy_slots,   copy,       { dst: i16, src: i16, len: u16 })
+            (fill_slots,   fill,       { dst: i16, len: u16, src: i16 })
+            // Linear memory, little-endian, out of bounds addresses trap
+            (load_u8,      ld8u,       { dst: i16, addr: i16 })
+            (load_s8,      ld8s,       { dst: i16, addr: i16 })
+            (load_u16,     ld16u,      { dst: i16, addr: i16 })
+            (load_s16,     ld16s,      { dst: i16, addr: i16 })
+            (load_u32,     ld32u,      { dst: i16, addr: i16 })
+            (load_s32,     ld32s,      { dst: i16, addr: i16 })
+            (load_64,      ld64,       { dst: i16, addr: i16 })
+            (load_f64,     ldf64,      { dst: i16, addr: i16 })
+            // Stores the low bits of the s64
+            (store_8,      st8,        { addr: i16, src: i16 })
+            (store_16,     st16,       { addr: i16, src: i16 })
+            (store_32,     st32,       { addr: i16, src: i16 })
+            (store_64,     st64,       { addr: i16, src: i16 })
+            (store_f64,    stf64,      { addr: i16, src: i16 })
+            (memory_size,  msize,      { dst: i16 })
+            // Captures the `len` slots starting at `src`
+            (new_closure,  closure,    { dst: i16, proc: u32 as Proc, src: i16, len: u16 })
+            // Capture `index` of the closure the proc was called with
+            (load_capture, ldcap,      { dst: i16, index: u16 })
+            // Replace the current frame, the parameters are passed on to the callee
+            (tail_call,    tcall,      { proc: u32 as Proc })
+            (tail_dynamic, tcall_dyn,  { src: i16 })
+            // Unwinds to the innermost handler of the exception tables
+            (throw,        throw,      { src: i16 })
         }
     };
 }
 
 macro_rules! create_constants {
-    ($( ($name: ident, $asm_name: ident, { $($arg_name: ident: $arg_type: ty),* }) )*) => {
+    ($( ($name: ident, $asm_name: ident, { $($arg_name: ident: $arg_type: ty $(as $arg_kind: ident)?),* }) )*) => {
         create_constants!(#paste[0] $(($name, $asm_name, {$($arg_name: $arg_type),*}))*);
     };
     (#paste[$opc: expr] ($name: ident, $asm_name: ident, {$($arg_name: ident: $arg_type: ty),*})
@@ -95,7 +264,7 @@ macro_rules! create_constants {
 }
 
 macro_rules! create_asm {
-    ($( ($name: ident, $asm_name: ident, { $($arg_name: ident: $arg_type: ty),* }) )*) => {
+    ($( ($name: ident, $asm_name: ident, { $($arg_name: ident: $arg_type: ty $(as $arg_kind: ident)?),* }) )*) => {
         pub mod _asm {
             pub fn todo<A, T>(_: A) -> T { todo!(); }
             create_asm!(#paste $(($name, $asm_name, {$($arg_name: $arg_type),*}))*);
@@ -117,11 +286,279 @@ macro_rules! create_asm {
     (#paste) => {};
 }
 
+macro_rules! create_info {
+    ($( ($name: ident, $asm_name: ident, { $($arg_name: ident: $arg_type: ty $(as $arg_kind: ident)?),* }) )*) => {
+        ::paste::paste! {
+            /// Metadata of all opcodes, indexed by opcode
+            pub static OPCODES: &[OpcodeInfo] = &[
+                $(
+                    OpcodeInfo {
+                        opcode: [<$name:upper>],
+                        mnemonic: stringify!($asm_name),
+                        operands: &[
+                            $(
+                                Operand {
+                                    name: stringify!($arg_name),
+                                    ty: OperandType::[<$arg_type:camel>],
+                                    kind: operand_kind!(OperandType::[<$arg_type:camel>] $(, $arg_kind)?),
+                                },
+                            )*
+                        ],
+                    },
+                )*
+            ];
+        }
+    };
+}
+
+/// Uses the kind declared with `as`, or the default kind of the operand type
+macro_rules! operand_kind {
+    ($ty: expr) => {
+        $ty.default_kind()
+    };
+    ($ty: expr, $kind: ident) => {
+        OperandKind::$kind
+    };
+}
+
 pub trait Instruction {
     fn write<T: Write>(&self, out: &mut T);
 
     fn read<T: Read>(src: &mut T) -> Self;
 }
 
+/// Describes the encoding of an opcode
+pub struct OpcodeInfo {
+    pub opcode: u8,
+    /// The name used in assembly
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+}
+
+impl OpcodeInfo {
+    /// Returns the encoded size of the instruction in bytes, including the opcode
+    pub fn size(&self) -> usize {
+        1 + self.operands.iter().map(|op| op.ty.size()).sum::<usize>()
+    }
+}
+
+pub struct Operand {
+    pub name: &'static str,
+    pub ty: OperandType,
+    /// What the operand refers to, declared in the opcode table
+    pub kind: OperandKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandKind {
+    /// A stack slot, see [Stack](crate::runtime::stack::Stack)
+    Slot,
+    /// A plain value
+    Immediate,
+    /// A branch offset relative to the end of the instruction
+    Offset,
+    /// An index into the proc table
+    Proc,
+    /// An index into the constant pool
+    Constant,
+    /// An index into the host function table
+    Host,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperandType {
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    /// Represented by its bits in an `i64`
+    F64,
+}
+
+impl OperandType {
+    /// Returns the kind of operands declared without one, `i16` operands address stack slots
+    pub const fn default_kind(self) -> OperandKind {
+        match self {
+            OperandType::I16 => OperandKind::Slot,
+            _ => OperandKind::Immediate,
+        }
+    }
+
+    /// Returns the encoded size in bytes
+    pub fn size(self) -> usize {
+        match self {
+            OperandType::I16 | OperandType::U16 => 2,
+            OperandType::I32 | OperandType::U32 => 4,
+            OperandType::I64 | OperandType::F64 => 8,
+        }
+    }
+
+    /// Returns the range of values representable by this type
+    ///
+    /// [OperandType::F64] covers all bit patterns.
+    pub fn range(self) -> (i64, i64) {
+        match self {
+            OperandType::I16 => (i16::MIN as i64, i16::MAX as i64),
+            OperandType::U16 => (u16::MIN as i64, u16::MAX as i64),
+            OperandType::I32 => (i32::MIN as i64, i32::MAX as i64),
+            OperandType::U32 => (u32::MIN as i64, u32::MAX as i64),
+            OperandType::I64 | OperandType::F64 => (i64::MIN, i64::MAX),
+        }
+    }
+
+    /// Writes `value` with this type's encoding
+    ///
+    /// The value must be in [OperandType::range].
+    pub fn write<T: Write>(self, out: &mut T, value: i64) {
+        match self {
+            OperandType::I16 => out.write_i16(value as i16),
+            OperandType::U16 => out.write_u16(value as u16),
+            OperandType::I32 => out.write_i32(value as i32),
+            OperandType::U32 => out.write_u32(value as u32),
+            OperandType::I64 | OperandType::F64 => out.write_i64(value),
+        }
+    }
+
+    /// Reads a value with this type's encoding from the start of `bytes`
+    ///
+    /// `bytes` must contain at least [OperandType::size] bytes.
+    pub fn read(self, bytes: &[u8]) -> i64 {
+        match self {
+            OperandType::I16 => i16::from_ne_bytes([bytes[0], bytes[1]]) as i64,
+            OperandType::U16 => u16::from_ne_bytes([bytes[0], bytes[1]]) as i64,
+            OperandType::I32 => i32::from_ne_bytes(bytes[..4].try_into().unwrap()) as i64,
+            OperandType::U32 => u32::from_ne_bytes(bytes[..4].try_into().unwrap()) as i64,
+            OperandType::I64 | OperandType::F64 => {
+                i64::from_ne_bytes(bytes[..8].try_into().unwrap())
+            }
+        }
+    }
+}
+
+impl fmt::Display for OperandType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OperandType::I16 => "i16",
+            OperandType::U16 => "u16",
+            OperandType::I32 => "i32",
+            OperandType::U32 => "u32",
+            OperandType::I64 => "i64",
+            OperandType::F64 => "f64",
+        })
+    }
+}
+
+/// An instruction decoded with the opcode table
+pub struct Decoded {
+    pub info: &'static OpcodeInfo,
+    /// Operand values in the order of [OpcodeInfo::operands]
+    pub operands: Vec<i64>,
+}
+
+impl Decoded {
+    /// Returns the encoded size in bytes
+    pub fn size(&self) -> usize {
+        self.info.size()
+    }
+
+    /// Returns the absolute target if this is a branch at `offset`
+    ///
+    /// The target may lie outside of the proc.
+    pub fn branch_target(&self, offset: usize) -> Option<i64> {
+        let index = self
+            .info
+            .operands
+            .iter()
+            .position(|operand| operand.kind == OperandKind::Offset)?;
+        Some((offset + self.size()) as i64 + self.operands[index])
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The opcode is not in the opcode table
+    UnknownOpcode(u8),
+    /// The code ends in the middle of the instruction
+    Truncated(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(opcode) => write!(f, "unknown opcode [0x{opcode:02x}]"),
+            DecodeError::Truncated(opcode) => match info(*opcode) {
+                Some(info) => write!(f, "truncated `{}` instruction", info.mnemonic),
+                None => write!(f, "truncated instruction [0x{opcode:02x}]"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes the instruction at the start of `code`
+///
+/// `code` must not be empty.
+pub fn decode(code: &[u8]) -> Result<Decoded, DecodeError> {
+    let opcode = code[0];
+    let info = info(opcode).ok_or(DecodeError::UnknownOpcode(opcode))?;
+    if code.len() < info.size() {
+        return Err(DecodeError::Truncated(opcode));
+    }
+    let mut offset = 1;
+    let operands = info
+        .operands
+        .iter()
+        .map(|operand| {
+            let value = operand.ty.read(&code[offset..]);
+            offset += operand.ty.size();
+            value
+        })
+        .collect();
+    Ok(Decoded { info, operands })
+}
+
+/// The result of [decode_all]
+pub struct Listing {
+    /// Instructions with their offsets
+    pub insns: Vec<(usize, Decoded)>,
+    /// The error that stopped decoding with its offset
+    pub error: Option<(usize, DecodeError)>,
+}
+
+/// Decodes instructions until the end of `code` or the first error
+pub fn decode_all(code: &[u8]) -> Listing {
+    let mut insns = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        match decode(&code[offset..]) {
+            Ok(insn) => {
+                let size = insn.size();
+                insns.push((offset, insn));
+                offset += size;
+            }
+            Err(err) => {
+                return Listing {
+                    insns,
+                    error: Some((offset, err)),
+                }
+            }
+        }
+    }
+    Listing { insns, error: None }
+}
+
+/// Returns the metadata of `opcode`
+pub fn info(opcode: u8) -> Option<&'static OpcodeInfo> {
+    OPCODES.get(opcode as usize)
+}
+
+/// Looks up an opcode by its assembly mnemonic
+pub fn lookup(mnemonic: &str) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| info.mnemonic == mnemonic)
+}
+
 opcodes!(create_constants);
 opcodes!(create_asm);
+opcodes!(create_info);
@@ -1,24 +1,70 @@
+pub mod builder;
 pub mod debug;
+pub mod heap;
+pub mod host;
+pub mod memory;
 pub mod proc;
 pub mod stack;
+pub mod trap;
 
-use std::{mem::size_of, ptr::null};
+use std::{mem::size_of, ptr::null, rc::Rc};
 
 use crate::{
+    module::format::LoadError,
     opcodes::{
-        AddF64, AddS64, Alloc, Branch, BranchGez, BranchGz, BranchLez, BranchLz, BranchNz, BranchZ,
-        Call, CallDynamic, DivF64, DivS64, Instruction, LoadConst, LoadProc, Move, MoveValue,
-        MulF64, MulS64, PrintF64, PrintProc, PrintS64, RemF64, RemS64, SubF64, SubS64, ADD_F64,
-        ADD_S64, ALLOC, BRANCH, BRANCH_GEZ, BRANCH_GZ, BRANCH_LEZ, BRANCH_LZ, BRANCH_NZ, BRANCH_Z,
-        BREAKPOINT, CALL, CALL_DYNAMIC, DIV_F64, DIV_S64, HALT, LOAD_CONST, LOAD_PROC, MOVE,
-        MOVE_VALUE, MUL_F64, MUL_S64, PRINT_F64, PRINT_PROC, PRINT_S64, REM_F64, REM_S64, RETURN,
-        SUB_F64, SUB_S64,
+        AddF64, AddS64, AddS64Imm, AddS64Sat, AddS64Wrap, Alloc, AndS64, ArrayLen, BitsToF64,
+        Branch, BranchEq, BranchEqf, BranchEqi, BranchGe, BranchGef, BranchGei, BranchGeu,
+        BranchGez, BranchGt, BranchGtf, BranchGti, BranchGtu, BranchGz, BranchLe, BranchLef,
+        BranchLei, BranchLeu, BranchLez, BranchLt, BranchLtf, BranchLti, BranchLtu, BranchLz,
+        BranchNanf, BranchNe, BranchNef, BranchNei, BranchNz, BranchUnof, BranchZ, Call,
+        CallDynamic, CallHost, CeilF64, ClzS64, CopySlots, CtzS64, DivF64, DivS64, DivU64, EqF64,
+        EqS64, F64ChkS64, F64SatS64, F64ToBits, F64ToS64, F64ToStr, FillSlots, FloorF64, GeF64,
+        GeS64, GeU64, GtF64, GtS64, GtU64, Instruction, IsNanF64, LeF64, LeS64, LeU64, Load64,
+        LoadCapture, LoadConst, LoadElem, LoadF64, LoadField, LoadIndex, LoadProc, LoadS16,
+        LoadS32, LoadS8, LoadU16, LoadU32, LoadU8, LtF64, LtS64, LtU64, MemorySize, Move, MoveF64,
+        MoveValue, MulF64, MulS64, MulS64Imm, MulS64Sat, MulS64Wrap, MulhS64, MulhU64, NeF64,
+        NeS64, NewArray, NewClosure, NewRecord, NotS64, OrS64, PopcntS64, PrintF64, PrintProc,
+        PrintRef, PrintS64, PrintStr, RemF64, RemS64, RemU64, RotlS64, RotrS64, RoundF64, S64ToF64,
+        S64ToStr, ShlS64, ShrS64, ShrU64, SignExt16, SignExt32, SignExt8, Store16, Store32,
+        Store64, Store8, StoreElem, StoreF64, StoreField, StoreIndex, StrByte, StrChar, StrChars,
+        StrCmp, StrConcat, StrLen, StrSub, StrToF64, StrToS64, SubF64, SubS64, SubS64Imm,
+        SubS64Sat, SubS64Wrap, TailCall, TailDynamic, Throw, TruncF64, UnoF64, XorS64, ZeroExt16,
+        ZeroExt32, ZeroExt8, ADD_F64, ADD_S64, ADD_S64_IMM, ADD_S64_SAT, ADD_S64_WRAP, ALLOC,
+        AND_S64, ARRAY_LEN, BITS_TO_F64, BRANCH, BRANCH_EQ, BRANCH_EQF, BRANCH_EQI, BRANCH_GE,
+        BRANCH_GEF, BRANCH_GEI, BRANCH_GEU, BRANCH_GEZ, BRANCH_GT, BRANCH_GTF, BRANCH_GTI,
+        BRANCH_GTU, BRANCH_GZ, BRANCH_LE, BRANCH_LEF, BRANCH_LEI, BRANCH_LEU, BRANCH_LEZ,
+        BRANCH_LT, BRANCH_LTF, BRANCH_LTI, BRANCH_LTU, BRANCH_LZ, BRANCH_NANF, BRANCH_NE,
+        BRANCH_NEF, BRANCH_NEI, BRANCH_NZ, BRANCH_UNOF, BRANCH_Z, BREAKPOINT, CALL, CALL_DYNAMIC,
+        CALL_HOST, CEIL_F64, CLZ_S64, COPY_SLOTS, CTZ_S64, DIV_F64, DIV_S64, DIV_U64, EQ_F64,
+        EQ_S64, F64_CHK_S64, F64_SAT_S64, F64_TO_BITS, F64_TO_S64, F64_TO_STR, FILL_SLOTS,
+        FLOOR_F64, GE_F64, GE_S64, GE_U64, GT_F64, GT_S64, GT_U64, HALT, IS_NAN_F64, LE_F64,
+        LE_S64, LE_U64, LOAD_64, LOAD_CAPTURE, LOAD_CONST, LOAD_ELEM, LOAD_F64, LOAD_FIELD,
+        LOAD_INDEX, LOAD_PROC, LOAD_S16, LOAD_S32, LOAD_S8, LOAD_U16, LOAD_U32, LOAD_U8, LT_F64,
+        LT_S64, LT_U64, MEMORY_SIZE, MOVE, MOVE_F64, MOVE_VALUE, MULH_S64, MULH_U64, MUL_F64,
+        MUL_S64, MUL_S64_IMM, MUL_S64_SAT, MUL_S64_WRAP, NEW_ARRAY, NEW_CLOSURE, NEW_RECORD,
+        NE_F64, NE_S64, NOT_S64, OR_S64, POPCNT_S64, PRINT_F64, PRINT_PROC, PRINT_REF, PRINT_S64,
+        PRINT_STR, REM_F64, REM_S64, REM_U64, RETURN, ROTL_S64, ROTR_S64, ROUND_F64, S64_TO_F64,
+        S64_TO_STR, SHL_S64, SHR_S64, SHR_U64, SIGN_EXT16, SIGN_EXT32, SIGN_EXT8, STORE_16,
+        STORE_32, STORE_64, STORE_8, STORE_ELEM, STORE_F64, STORE_FIELD, STORE_INDEX, STR_BYTE,
+        STR_CHAR, STR_CHARS, STR_CMP, STR_CONCAT, STR_LEN, STR_SUB, STR_TO_F64, STR_TO_S64,
+        SUB_F64, SUB_S64, SUB_S64_IMM, SUB_S64_SAT, SUB_S64_WRAP, TAIL_CALL, TAIL_DYNAMIC, THROW,
+        TRUNC_F64, UNO_F64, XOR_S64, ZERO_EXT16, ZERO_EXT32, ZERO_EXT8,
     },
     util::Read,
     value,
+    value::{Tag, Value},
+    verify::{verify_proc, Tables, VerifyError},
 };
 
-use self::{proc::Proc, stack::Stack};
+use self::{
+    builder::RuntimeBuilder,
+    heap::{Heap, Object},
+    host::{Host, HostFn},
+    memory::Memory,
+    proc::Proc,
+    stack::Stack,
+    trap::{Location, Trap, TrapKind},
+};
 
 #[macro_export]
 macro_rules! make_runtime {
@@ -48,274 +94,2751 @@ pub struct Runtime {
     /// Program counter
     pc: *const u8,
     stack: Stack,
+    heap: Heap,
+    memory: Memory,
     constants: Vec<Constant>,
+    /// Heap strings of the string constants, allocated on first load
+    const_strs: Vec<Option<u32>>,
+    /// Boxed to keep proc addresses stable while pushing
+    #[allow(clippy::vec_box)]
     procs: Vec<Box<Proc>>,
+    /// Whether each proc passed verification, indexed like `procs`
+    verified: Vec<bool>,
+    hosts: Vec<Host>,
+    /// Whether handlers catch traps, see [RuntimeBuilder::catch_traps]
+    catch_traps: bool,
+    /// Number of host functions being called
+    host_depth: usize,
+    /// A thrown value unwinding through host functions, kept as a root until it is caught
+    thrown: Option<Value>,
 }
 
 impl Runtime {
     pub fn new() -> Self {
-        Self {
-            pc: null(),
-            stack: Stack::new(4096),
-            constants: Vec::new(),
-            procs: Vec::new(),
-        }
+        RuntimeBuilder::new().build()
+    }
+
+    pub fn builder() -> RuntimeBuilder {
+        RuntimeBuilder::new()
+    }
+
+    /// Loads and verifies a module file, see [format](crate::module::format)
+    pub fn load(bytes: &[u8]) -> Result<Self, LoadError> {
+        RuntimeBuilder::new().load(bytes)
     }
 
     pub fn push_constant(&mut self, constant: Constant) {
         self.constants.push(constant);
+        self.const_strs.push(None);
     }
 
+    /// Adds a proc, which is verified before it is first called
     pub fn push_proc(&mut self, proc: Proc) {
         self.procs.push(Box::new(proc));
+        self.verified.push(false);
+    }
+
+    /// Verifies all procs that did not pass verification yet
+    ///
+    /// Indices are checked against the current tables, which only grow, so verified procs
+    /// stay valid. Calls verify pending procs themselves, this reports errors up front.
+    pub fn verify(&mut self) -> Result<(), VerifyError> {
+        for index in 0..self.procs.len() {
+            self.verify_one(index)?;
+        }
+        Ok(())
+    }
+
+    /// Verifies proc `index` unless it already passed verification
+    fn verify_one(&mut self, index: usize) -> Result<(), VerifyError> {
+        if self.verified[index] {
+            return Ok(());
+        }
+        let tables = Tables {
+            procs: self.procs.len(),
+            constants: self.constants.len(),
+            hosts: self.hosts.len(),
+        };
+        verify_proc(&self.procs[index], index as u32, &tables)?;
+        self.verified[index] = true;
+        Ok(())
+    }
+
+    /// Returns proc `index`, verifying it before it is first called
+    ///
+    /// Only the callee is verified, so a pending proc that fails verification only traps
+    /// when it is called itself.
+    #[inline]
+    fn callee(&mut self, index: u32) -> Result<*const Proc, Trap> {
+        match self.verified.get(index as usize) {
+            Some(true) => {}
+            Some(false) => self.verify_one(index as usize).map_err(TrapKind::Verify)?,
+            None => return Err(TrapKind::BadProcIndex(index).into()),
+        }
+        Ok(&*self.procs[index as usize])
+    }
+
+    /// Returns all procs in the order they were pushed
+    pub fn procs(&self) -> impl ExactSizeIterator<Item = &Proc> {
+        self.procs.iter().map(|proc| &**proc)
+    }
+
+    pub fn constants(&self) -> &[Constant] {
+        &self.constants
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Reads the `N` bytes at `addr` of the linear memory
+    #[inline]
+    pub fn read_memory<const N: usize>(&self, addr: i64) -> Result<[u8; N], Trap> {
+        self.memory.read(addr).ok_or_else(|| bad_address(addr, N))
+    }
+
+    /// Writes `bytes` at `addr` of the linear memory
+    #[inline]
+    pub fn write_memory<const N: usize>(&mut self, addr: i64, bytes: [u8; N]) -> Result<(), Trap> {
+        self.memory
+            .write(addr, bytes)
+            .ok_or_else(|| bad_address(addr, N))
+    }
+
+    /// Frees all objects that are not reachable from the stack or the constant pool
+    ///
+    /// Returns the number of freed objects.
+    pub fn collect_garbage(&mut self) -> usize {
+        let stack = self.stack.values().filter_map(Value::as_object);
+        let constants = self.const_strs.iter().flatten().copied();
+        let thrown = self.thrown.and_then(Value::as_object);
+        self.heap.collect(stack.chain(constants).chain(thrown))
+    }
+
+    /// Adds `object` to the heap, collecting garbage first if the heap is large enough
+    pub fn alloc_object(&mut self, object: Object) -> Result<u32, Trap> {
+        self.reserve_heap(object.size())?;
+        Ok(self.heap.alloc(object))
+    }
+
+    /// Makes sure an object of `size` values fits into the heap
+    fn reserve_heap(&mut self, size: usize) -> Result<(), Trap> {
+        if self.heap.should_collect(size) || !self.heap.has_space(size) {
+            self.collect_garbage();
+        }
+        if !self.heap.has_space(size) {
+            return Err(TrapKind::OutOfMemory.into());
+        }
+        Ok(())
+    }
+
+    pub fn object(&self, index: u32) -> Result<&Object, Trap> {
+        self.heap
+            .get(index)
+            .ok_or_else(|| TrapKind::BadRef(index).into())
+    }
+
+    pub fn object_mut(&mut self, index: u32) -> Result<&mut Object, Trap> {
+        self.heap
+            .get_mut(index)
+            .ok_or_else(|| TrapKind::BadRef(index).into())
+    }
+
+    /// Returns the referenced string
+    pub fn str(&self, index: u32) -> Result<&str, Trap> {
+        self.object(index)?
+            .as_str()
+            .ok_or_else(|| TrapKind::BadRef(index).into())
+    }
+
+    /// Adds a string to the heap, collecting garbage first if the heap is large enough
+    pub fn alloc_str(&mut self, str: impl Into<Box<str>>) -> Result<u32, Trap> {
+        self.alloc_object(Object::Str(str.into()))
+    }
+
+    /// Loads field `field` of the referenced object
+    pub fn load_field(&self, object: u32, field: i64) -> Result<Value, Trap> {
+        let fields = self.object(object)?.fields();
+        usize::try_from(field)
+            .ok()
+            .and_then(|field| fields.get(field).copied())
+            .ok_or_else(|| out_of_bounds(field, fields.len()))
+    }
+
+    /// Stores a value in field `field` of the referenced object
+    pub fn store_field(&mut self, object: u32, field: i64, value: Value) -> Result<(), Trap> {
+        let fields = self.object_mut(object)?.fields_mut();
+        let len = fields.len();
+        let slot = usize::try_from(field)
+            .ok()
+            .and_then(|field| fields.get_mut(field))
+            .ok_or_else(|| out_of_bounds(field, len))?;
+        *slot = value;
+        Ok(())
+    }
+
+    /// Registers `func` as the host function called `name` and returns its index
+    ///
+    /// This binds all host functions with that name that were pushed by [Runtime::push_host]
+    /// or else adds a new one.
+    pub fn register_host(
+        &mut self,
+        name: impl Into<String>,
+        func: impl Fn(&mut Runtime) -> Result<(), Trap> + 'static,
+    ) -> u32 {
+        let name = name.into();
+        let func: Rc<HostFn> = Rc::new(func);
+        let mut index = None;
+        for (i, host) in self.hosts.iter_mut().enumerate() {
+            if host.name == name {
+                host.func = Some(func.clone());
+                index.get_or_insert(i as u32);
+            }
+        }
+        index.unwrap_or_else(|| {
+            self.hosts.push(Host {
+                name,
+                func: Some(func),
+            });
+            self.hosts.len() as u32 - 1
+        })
+    }
+
+    /// Adds a host function without a function registered for it yet
+    pub fn push_host(&mut self, name: impl Into<String>) -> u32 {
+        self.hosts.push(Host {
+            name: name.into(),
+            func: None,
+        });
+        self.hosts.len() as u32 - 1
+    }
+
+    /// Returns the names of all host functions in the order they were added
+    pub fn host_names(&self) -> impl ExactSizeIterator<Item = &str> {
+        self.hosts.iter().map(|host| &*host.name)
     }
 
-    pub fn call(&mut self, index: u32) {
-        let Some(proc) = self.procs.get(index as usize) else {
-            panic!("Unable to call proc #{index}");
+    /// Calls a host function in a new frame
+    ///
+    /// The host function must return with its frame on top of the stack. Otherwise the
+    /// program is stopped and [TrapKind::HostFrame] is returned.
+    pub fn call_host(&mut self, index: u32) -> Result<(), Trap> {
+        let Some(host) = self.hosts.get(index as usize) else {
+            return Err(TrapKind::BadHostIndex(index).into());
+        };
+        let Some(func) = host.func.clone() else {
+            return Err(TrapKind::UnboundHost(host.name.clone()).into());
         };
-        unsafe { self.push_call_frame(&**proc) };
+        self.stack.push_frame(self.pc)?;
+        let mark = self.stack.mark();
+        self.host_depth += 1;
+        let result = func(self);
+        self.host_depth -= 1;
+        if self.stack.mark() != mark {
+            // The frame was discarded by `reset` or is buried under frames pushed by `call`
+            self.reset();
+            return Err(result.err().unwrap_or_else(|| TrapKind::HostFrame.into()));
+        }
+        self.stack.return_call()?;
+        if result.is_ok() {
+            // The host function swallowed any value thrown through it
+            self.thrown = None;
+        }
+        result
     }
 
-    pub fn load_const(&mut self, dst: i16, index: u32) {
+    /// Stops the program
+    ///
+    /// When called from a host function, the program stops once it returns.
+    pub fn halt(&mut self) {
+        self.pc = null();
+    }
+
+    /// Returns the index of the proc called `name`
+    pub fn find_proc(&self, name: &str) -> Option<u32> {
+        self.procs
+            .iter()
+            .position(|proc| proc.name.as_deref() == Some(name))
+            .map(|index| index as u32)
+    }
+
+    /// Calls a proc with `args` and runs until it returns
+    ///
+    /// `args[i]` is passed in slot `-1 - i`. The same slots are returned after the call, so
+    /// there must be at least as many arguments as the proc has return values.
+    ///
+    /// The runtime is restored to its previous state afterwards, even on a trap, so this can
+    /// be called repeatedly and from host functions. If the program halts before the proc
+    /// returns, [TrapKind::Halted] is returned.
+    ///
+    /// Called from a host function, an uncaught value thrown by the proc is returned as
+    /// [TrapKind::Thrown], so returning the trap lets the handlers of the guest code that
+    /// called the host function catch it.
+    pub fn invoke(&mut self, index: u32, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        let proc = self.callee(index)?;
+        let pc = self.pc;
+        let mark = self.stack.mark();
+        let result = self.invoke_proc(proc, args);
+        self.pc = pc;
+        unsafe { self.stack.reset(mark) };
+        result
+    }
+
+    fn invoke_proc(&mut self, proc: *const Proc, args: &[Value]) -> Result<Vec<Value>, Trap> {
+        self.stack.alloc(args.len())?;
+        let args_mark = self.stack.mark();
+        let depth = self.stack.depth();
+        for (i, arg) in args.iter().enumerate() {
+            self.stack.store_at(depth - i, *arg);
+        }
+        unsafe {
+            // Returning to a null address stops `run`
+            self.pc = null();
+            self.push_call_frame(proc)?;
+        }
+        self.run()?;
+        if self.stack.mark() != args_mark {
+            return Err(TrapKind::Halted.into());
+        }
+        // The argument slots are below the popped frame, so they are still initialized
+        Ok((0..args.len())
+            .map(|i| self.stack.value_at(depth - i).unwrap())
+            .collect())
+    }
+
+    /// Discards all frames and stops the program
+    ///
+    /// This makes the runtime usable again after [Runtime::run] returned a trap.
+    pub fn reset(&mut self) {
+        self.pc = null();
+        self.stack.clear();
+    }
+
+    pub fn call(&mut self, index: u32) -> Result<(), Trap> {
+        let proc = self.callee(index)?;
+        unsafe { self.push_call_frame(proc) }
+    }
+
+    pub fn load_const(&mut self, dst: i16, index: u32) -> Result<(), Trap> {
         let Some(constant) = self.constants.get(index as usize) else {
-            panic!("Unable to load constant #{index}");
+            return Err(TrapKind::BadConstIndex(index).into());
         };
-        self.stack.store(
-            dst,
-            match constant {
-                Constant::S64(value) => value!(@s64 *value),
-                Constant::F64(value) => value!(@f64 *value),
-            },
-        );
+        let value = match constant {
+            Constant::S64(value) => value!(@s64 *value),
+            Constant::F64(value) => value!(@f64 *value),
+            Constant::Str(_) => value!(@str self.const_str(index)?),
+        };
+        self.store_slot(dst, value)
     }
 
-    pub fn load_proc(&mut self, dst: i16, index: u32) {
-        let Some(proc) = self.procs.get(index as usize) else {
-            panic!("Unable to load proc #{index}");
+    /// Returns the heap string of string constant `index`, allocating it on first use
+    fn const_str(&mut self, index: u32) -> Result<u32, Trap> {
+        if let Some(object) = self.const_strs[index as usize] {
+            return Ok(object);
+        }
+        let Constant::Str(value) = &self.constants[index as usize] else {
+            unreachable!("constant #{index} is not a string");
         };
-        self.stack.store(dst, value!(@proc &**proc));
+        let value: Box<str> = value.as_str().into();
+        let object = self.alloc_str(value)?;
+        self.const_strs[index as usize] = Some(object);
+        Ok(object)
+    }
+
+    /// Calls proc `index`, passing `closure` to read the captures from
+    pub fn call_closure(&mut self, index: u32, closure: Option<u32>) -> Result<(), Trap> {
+        self.call(index)?;
+        if let Some(closure) = closure {
+            self.stack.set_closure(closure);
+        }
+        Ok(())
+    }
+
+    /// Replaces the current frame with a call of proc `index`, passing `closure`
+    ///
+    /// The parameters of the current frame become the parameters of the callee.
+    pub fn tail_call(&mut self, index: u32, closure: Option<u32>) -> Result<(), Trap> {
+        let proc = self.callee(index)?;
+        self.pc = unsafe { (*proc).code.as_ptr() };
+        self.stack.reuse_frame();
+        if let Some(closure) = closure {
+            self.stack.set_closure(closure);
+        }
+        Ok(())
+    }
+
+    pub fn load_proc(&mut self, dst: i16, index: u32) -> Result<(), Trap> {
+        if index as usize >= self.procs.len() {
+            return Err(TrapKind::BadProcIndex(index).into());
+        }
+        self.store_slot(dst, value!(@proc index))
+    }
+
+    /// Returns the proc and offset of the instruction at `pc`
+    pub fn locate(&self, pc: *const u8) -> Option<Location> {
+        self.procs.iter().enumerate().find_map(|(index, proc)| {
+            let offset = (pc as usize).checked_sub(proc.code.as_ptr() as usize)?;
+            (offset < proc.code.len()).then(|| Location {
+                proc: index as u32,
+                name: proc.name.clone(),
+                offset,
+            })
+        })
     }
 
     /// Pushes a call frame and sets the instruction pointer
-    pub unsafe fn push_call_frame(&mut self, proc: *const Proc) {
+    ///
+    /// # Safety
+    ///
+    /// `proc` must point to a live [Proc] that passed verification.
+    pub unsafe fn push_call_frame(&mut self, proc: *const Proc) -> Result<(), Trap> {
         unsafe {
-            self.stack.push_frame(self.pc);
+            self.stack.push_frame(self.pc)?;
             self.pc = (*proc).code.as_ptr();
         }
+        Ok(())
     }
 
+    /// Loads the value at the given stack slot of the current frame
     #[inline]
-    pub fn branch_rel(&mut self, offset: i32) {
-        unsafe {
-            self.pc = self.pc.offset(offset as isize);
+    pub fn load_slot(&self, slot: i16) -> Result<Value, Trap> {
+        Ok(self.stack.load(slot)?)
+    }
+
+    /// Loads the s64 at the given stack slot of the current frame
+    #[inline]
+    pub fn load_slot_s64(&self, slot: i16) -> Result<i64, Trap> {
+        expect_s64(slot, self.load_slot(slot)?)
+    }
+
+    /// Loads the f64 at the given stack slot of the current frame
+    #[inline]
+    pub fn load_slot_f64(&self, slot: i16) -> Result<f64, Trap> {
+        expect_f64(slot, self.load_slot(slot)?)
+    }
+
+    /// Loads the object reference at the given stack slot of the current frame
+    #[inline]
+    pub fn load_slot_ref(&self, slot: i16) -> Result<u32, Trap> {
+        expect_ref(slot, self.load_slot(slot)?)
+    }
+
+    /// Loads the string reference at the given stack slot of the current frame
+    #[inline]
+    pub fn load_slot_str(&self, slot: i16) -> Result<u32, Trap> {
+        expect_str(slot, self.load_slot(slot)?)
+    }
+
+    /// Loads the proc index at the given stack slot of the current frame
+    #[inline]
+    pub fn load_slot_proc(&self, slot: i16) -> Result<u32, Trap> {
+        expect_proc(slot, self.load_slot(slot)?)
+    }
+
+    /// Loads the proc or closure at the given stack slot of the current frame
+    ///
+    /// Returns the proc index and the closure, if any.
+    pub fn load_slot_callee(&self, slot: i16) -> Result<(u32, Option<u32>), Trap> {
+        self.expect_callee(slot, self.load_slot(slot)?)
+    }
+
+    fn expect_callee(&self, slot: i16, value: Value) -> Result<(u32, Option<u32>), Trap> {
+        match value {
+            Value::Proc(index) => Ok((index, None)),
+            Value::Closure(closure) => match self.object(closure)? {
+                Object::Closure { proc, .. } => Ok((*proc, Some(closure))),
+                _ => Err(TrapKind::BadRef(closure).into()),
+            },
+            value => Err(type_mismatch(slot, Tag::Proc, value)),
         }
     }
 
-    pub fn fetch(&mut self) -> u8 {
-        self.read::<u8>()
+    /// Stores a value at the given stack slot of the current frame
+    #[inline]
+    pub fn store_slot(&mut self, slot: i16, value: Value) -> Result<(), Trap> {
+        Ok(self.stack.store(slot, value)?)
     }
 
-    pub fn execute(&mut self, opcode: u8) {
-        unsafe {
-            match opcode {
-                ALLOC => {
-                    let insn = Alloc::read(self);
-                    self.stack.alloc(insn.size as usize);
-                }
-                MOVE => {
-                    let insn = Move::read(self);
-                    let value = self.stack.load(insn.src);
-                    self.stack.store(insn.dst, value);
-                }
-                MOVE_VALUE => {
-                    let insn = MoveValue::read(self);
-                    self.stack.store(insn.dst, value!(@s64 insn.value));
-                }
-                LOAD_CONST => {
-                    let insn = LoadConst::read(self);
-                    self.load_const(insn.dst, insn.index);
-                }
-                LOAD_PROC => {
-                    let insn = LoadProc::read(self);
-                    self.load_proc(insn.dst, insn.index);
-                }
-                CALL => {
-                    let insn = Call::read(self);
-                    self.call(insn.index);
-                }
-                CALL_DYNAMIC => {
-                    let insn = CallDynamic::read(self);
-                    let proc = self.stack.load(insn.src).proc;
-                    self.push_call_frame(proc);
-                }
-                BRANCH => {
-                    let insn = Branch::read(self);
-                    self.branch_rel(insn.offset);
-                }
-                BRANCH_Z => {
-                    let insn = BranchZ::read(self);
-                    if self.stack.load(insn.src).s64 == 0 {
-                        self.branch_rel(insn.offset);
-                    }
-                }
-                BRANCH_NZ => {
-                    let insn = BranchNz::read(self);
-                    if self.stack.load(insn.src).s64 != 0 {
-                        self.branch_rel(insn.offset);
-                    }
-                }
-                BRANCH_LZ => {
-                    let insn = BranchLz::read(self);
-                    if self.stack.load(insn.src).s64 < 0 {
-                        self.branch_rel(insn.offset);
-                    }
-                }
-                BRANCH_LEZ => {
-                    let insn = BranchLez::read(self);
-                    if self.stack.load(insn.src).s64 <= 0 {
-                        self.branch_rel(insn.offset);
-                    }
-                }
-                BRANCH_GZ => {
-                    let insn = BranchGz::read(self);
-                    if self.stack.load(insn.src).s64 > 0 {
-                        self.branch_rel(insn.offset);
-                    }
-                }
-                BRANCH_GEZ => {
-                    let insn = BranchGez::read(self);
-                    if self.stack.load(insn.src).s64 >= 0 {
-                        self.branch_rel(insn.offset);
-                    }
-                }
-                RETURN => {
-                    let ra = self.stack.return_call();
-                    self.pc = ra;
-                }
-                ADD_S64 => {
-                    let insn = AddS64::read(self);
-                    let left = self.stack.load(insn.left).s64;
-                    let right = self.stack.load(insn.right).s64;
-                    self.stack.store(insn.dst, value!(@s64 left + right));
-                }
-                SUB_S64 => {
-                    let insn = SubS64::read(self);
-                    let left = self.stack.load(insn.left).s64;
-                    let right = self.stack.load(insn.right).s64;
-                    self.stack.store(insn.dst, value!(@s64 left - right));
-                }
-                MUL_S64 => {
-                    let insn = MulS64::read(self);
-                    let left = self.stack.load(insn.left).s64;
-                    let right = self.stack.load(insn.right).s64;
-                    self.stack.store(insn.dst, value!(@s64 left * right));
-                }
-                DIV_S64 => {
-                    let insn = DivS64::read(self);
-                    let left = self.stack.load(insn.left).s64;
-                    let right = self.stack.load(insn.right).s64;
-                    self.stack.store(insn.dst, value!(@s64 left / right));
-                }
-                REM_S64 => {
-                    let insn = RemS64::read(self);
-                    let left = self.stack.load(insn.left).s64;
-                    let right = self.stack.load(insn.right).s64;
-                    self.stack.store(insn.dst, value!(@s64 left % right));
-                }
-                ADD_F64 => {
-                    let insn = AddF64::read(self);
-                    let left = self.stack.load(insn.left).f64;
-                    let right = self.stack.load(insn.right).f64;
-                    self.stack.store(insn.dst, value!(@f64 left + right));
-                }
-                SUB_F64 => {
-                    let insn = SubF64::read(self);
-                    let left = self.stack.load(insn.left).f64;
-                    let right = self.stack.load(insn.right).f64;
-                    self.stack.store(insn.dst, value!(@f64 left - right));
-                }
-                MUL_F64 => {
-                    let insn = MulF64::read(self);
-                    let left = self.stack.load(insn.left).f64;
-                    let right = self.stack.load(insn.right).f64;
-                    self.stack.store(insn.dst, value!(@f64 left * right));
-                }
-                DIV_F64 => {
-                    let insn = DivF64::read(self);
-                    let left = self.stack.load(insn.left).f64;
-                    let right = self.stack.load(insn.right).f64;
-                    self.stack.store(insn.dst, value!(@f64 left / right));
-                }
-                REM_F64 => {
-                    let insn = RemF64::read(self);
-                    let left = self.stack.load(insn.left).f64;
-                    let right = self.stack.load(insn.right).f64;
-                    self.stack.store(insn.dst, value!(@f64 left % right));
-                }
-                PRINT_S64 => {
-                    let insn = PrintS64::read(self);
-                    let value = self.stack.load(insn.src).s64;
-                    println!("{value}");
-                }
-                PRINT_F64 => {
-                    let insn = PrintF64::read(self);
-                    let value = self.stack.load(insn.src).f64;
-                    println!("{value}");
-                }
-                PRINT_PROC => {
-                    let insn = PrintProc::read(self);
-                    let value = self.stack.load(insn.src).proc;
-                    println!("<proc:{value:?}>");
-                }
-                HALT => {
-                    self.pc = null();
-                }
-                BREAKPOINT => (),
-                _ => unimplemented!("opcode [0x{opcode:02x}]"),
-            }
-        }
-    }
-
-    pub fn run(&mut self) {
-        while !self.pc.is_null() {
-            let opcode = self.fetch();
-            self.execute(opcode);
+    /// Loads a slot operand of the executing instruction
+    ///
+    /// With the `unchecked-stack` feature only parameter slots are bounds checked, verified
+    /// procs only use local slots inside their frame.
+    #[inline]
+    fn read_slot(&self, slot: i16) -> Result<Value, Trap> {
+        if cfg!(feature = "unchecked-stack") {
+            self.stack.check_param(slot)?;
+            Ok(unsafe { self.stack.load_unchecked(slot)? })
+        } else {
+            self.load_slot(slot)
         }
     }
 
-    pub fn run_debug(&mut self) {
-        while !self.pc.is_null() {
-            let opcode = self.fetch();
-            self.execute(opcode);
+    #[inline]
+    fn read_slot_s64(&self, slot: i16) -> Result<i64, Trap> {
+        expect_s64(slot, self.read_slot(slot)?)
+    }
+
+    #[inline]
+    fn read_slot_f64(&self, slot: i16) -> Result<f64, Trap> {
+        expect_f64(slot, self.read_slot(slot)?)
+    }
+
+    #[inline]
+    fn read_slot_ref(&self, slot: i16) -> Result<u32, Trap> {
+        expect_ref(slot, self.read_slot(slot)?)
+    }
+
+    #[inline]
+    fn read_slot_str(&self, slot: i16) -> Result<u32, Trap> {
+        expect_str(slot, self.read_slot(slot)?)
+    }
+
+    fn read_slot_callee(&self, slot: i16) -> Result<(u32, Option<u32>), Trap> {
+        self.expect_callee(slot, self.read_slot(slot)?)
+    }
+
+    /// Stores to a slot operand of the executing instruction, see [Runtime::read_slot]
+    #[inline]
+    fn write_slot(&mut self, slot: i16, value: Value) -> Result<(), Trap> {
+        if cfg!(feature = "unchecked-stack") {
+            self.stack.check_param(slot)?;
+            unsafe { self.stack.store_unchecked(slot, value) };
+            Ok(())
+        } else {
+            self.store_slot(slot, value)
         }
     }
-}
 
-impl Read for Runtime {
     #[inline]
-    fn read<T: Copy>(&mut self) -> T {
+    fn branch_rel(&mut self, offset: i32) {
         unsafe {
-            let value = (self.pc as *const T).read_unaligned();
-            self.pc = self.pc.add(size_of::<T>());
-            value
+            self.pc = self.pc.offset(offset as isize);
         }
     }
-}
 
-pub enum Constant {
-    S64(i64),
-    F64(f64),
-}
+    /// Reads the opcode at the program counter, which must not be null
+    pub(crate) fn fetch(&mut self) -> u8 {
+        self.operands().read::<u8>()
+    }
 
-impl From<i64> for Constant {
-    fn from(value: i64) -> Self {
-        Self::S64(value)
+    pub(crate) fn operands(&mut self) -> Operands<'_> {
+        Operands(&mut self.pc)
     }
-}
 
-impl From<f64> for Constant {
-    fn from(value: f64) -> Self {
-        Self::F64(value)
+    /// Executes the instruction whose opcode was just fetched
+    ///
+    /// On a trap the program counter is left at the faulting instruction. Thrown values and,
+    /// with [RuntimeBuilder::catch_traps], traps continue at an exception handler instead.
+    pub(crate) fn execute(&mut self, opcode: u8) -> Result<(), Trap> {
+        let start = self.pc.wrapping_sub(1);
+        let Err(trap) = self.execute_insn(opcode) else {
+            return Ok(());
+        };
+        self.trap_at(start, trap)
+    }
+
+    /// Handles a trap raised by the instruction at `start`, see [Runtime::execute]
+    #[cold]
+    pub(crate) fn trap_at(&mut self, start: *const u8, mut trap: Trap) -> Result<(), Trap> {
+        self.pc = start;
+        if trap.location.is_none() {
+            trap.location = self.locate(start);
+        }
+        self.handle_trap(trap)
+    }
+
+    /// Unwinds to the handler of a thrown value or, if enabled, a trap
+    ///
+    /// The program counter must point at the instruction that raised the trap. A thrown value
+    /// without a handler is passed on to the caller of the host function that invoked the
+    /// proc, and is only reported as [TrapKind::Uncaught] outside of host functions.
+    #[cold]
+    fn handle_trap(&mut self, trap: Trap) -> Result<(), Trap> {
+        let Some((frames, handler, slot)) = self.find_handler(self.pc) else {
+            if let TrapKind::Thrown(value) = trap.kind {
+                if self.host_depth > 0 {
+                    self.thrown = Some(value);
+                    return Err(trap);
+                }
+                self.thrown = None;
+                return Err(Trap {
+                    kind: TrapKind::Uncaught(self.describe(value)),
+                    location: trap.location,
+                });
+            }
+            return Err(trap);
+        };
+        let value = match &trap.kind {
+            TrapKind::Thrown(value) => *value,
+            kind if self.catch_traps => match self.alloc_str(kind.to_string()) {
+                Ok(str) => value!(@str str),
+                Err(_) => return Err(trap),
+            },
+            _ => return Err(trap),
+        };
+        for _ in 0..frames {
+            self.stack.return_call()?;
+        }
+        self.pc = handler;
+        self.thrown = None;
+        self.store_slot(slot, value)
+    }
+
+    /// Finds the innermost handler for the instruction at `pc`
+    ///
+    /// Returns the number of frames to pop, the address of the handler and its slot. The
+    /// search stops at the frame pushed by [Runtime::invoke].
+    fn find_handler(&self, pc: *const u8) -> Option<(usize, *const u8, i16)> {
+        let mut at = pc;
+        for (frames, ra) in self.stack.return_addresses().enumerate() {
+            let location = self.locate(at)?;
+            let proc = &self.procs[location.proc as usize];
+            if let Some(handler) = proc.find_handler(location.offset) {
+                let target = unsafe { proc.code.as_ptr().add(handler.target as usize) };
+                return Some((frames, target, handler.slot));
+            }
+            if ra.is_null() {
+                return None;
+            }
+            // Return addresses point after the call instruction
+            at = ra.wrapping_sub(1);
+        }
+        None
+    }
+
+    /// Renders `value` for messages, showing the contents of heap objects
+    fn describe(&self, value: Value) -> String {
+        match value.as_object().and_then(|index| self.heap.get(index)) {
+            Some(object) => object.to_string(),
+            None => value.to_string(),
+        }
+    }
+
+    fn execute_insn(&mut self, opcode: u8) -> Result<(), Trap> {
+        match opcode {
+            ALLOC => {
+                let insn = Alloc::read(&mut self.operands());
+                self.stack.alloc(insn.size as usize)?;
+            }
+            MOVE => {
+                let insn = Move::read(&mut self.operands());
+                let value = self.read_slot(insn.src)?;
+                self.write_slot(insn.dst, value)?;
+            }
+            MOVE_VALUE => {
+                let insn = MoveValue::read(&mut self.operands());
+                self.write_slot(insn.dst, value!(@s64 insn.value))?;
+            }
+            MOVE_F64 => {
+                let insn = MoveF64::read(&mut self.operands());
+                self.write_slot(insn.dst, value!(@f64 insn.value))?;
+            }
+            LOAD_CONST => {
+                let insn = LoadConst::read(&mut self.operands());
+                self.load_const(insn.dst, insn.constant)?;
+            }
+            LOAD_PROC => {
+                let insn = LoadProc::read(&mut self.operands());
+                self.load_proc(insn.dst, insn.proc)?;
+            }
+            NEW_CLOSURE => {
+                let insn = NewClosure::read(&mut self.operands());
+                if insn.proc as usize >= self.procs.len() {
+                    return Err(TrapKind::BadProcIndex(insn.proc).into());
+                }
+                self.stack.check_range(insn.src, insn.len as usize)?;
+                let captures = (0..insn.len as i16)
+                    .map(|i| self.read_slot(insn.src + i))
+                    .collect::<Result<Box<[Value]>, Trap>>()?;
+                let closure = self.alloc_object(Object::Closure {
+                    proc: insn.proc,
+                    captures,
+                })?;
+                self.write_slot(insn.dst, value!(@closure closure))?;
+            }
+            LOAD_CAPTURE => {
+                let insn = LoadCapture::read(&mut self.operands());
+                let Some(closure) = self.stack.closure() else {
+                    return Err(TrapKind::NoClosure.into());
+                };
+                let value = self.load_field(closure, insn.index as i64)?;
+                self.write_slot(insn.dst, value)?;
+            }
+            LOAD_INDEX => {
+                let insn = LoadIndex::read(&mut self.operands());
+                let index = self.read_slot_s64(insn.index)?;
+                let slot = self.stack.index_slot(insn.base, index)?;
+                let value = self.read_slot(slot)?;
+                self.write_slot(insn.dst, value)?;
+            }
+            STORE_INDEX => {
+                let insn = StoreIndex::read(&mut self.operands());
+                let index = self.read_slot_s64(insn.index)?;
+                let slot = self.stack.index_slot(insn.base, index)?;
+                let value = self.read_slot(insn.src)?;
+                self.write_slot(slot, value)?;
+            }
+            COPY_SLOTS => {
+                let insn = CopySlots::read(&mut self.operands());
+                self.stack.copy(insn.dst, insn.src, insn.len as usize)?;
+            }
+            FILL_SLOTS => {
+                let insn = FillSlots::read(&mut self.operands());
+                let value = self.read_slot(insn.src)?;
+                self.stack.fill(insn.dst, insn.len as usize, value)?;
+            }
+            CALL => {
+                let insn = Call::read(&mut self.operands());
+                self.call(insn.proc)?;
+            }
+            CALL_DYNAMIC => {
+                let insn = CallDynamic::read(&mut self.operands());
+                let (proc, closure) = self.read_slot_callee(insn.src)?;
+                self.call_closure(proc, closure)?;
+            }
+            TAIL_CALL => {
+                let insn = TailCall::read(&mut self.operands());
+                self.tail_call(insn.proc, None)?;
+            }
+            TAIL_DYNAMIC => {
+                let insn = TailDynamic::read(&mut self.operands());
+                let (proc, closure) = self.read_slot_callee(insn.src)?;
+                self.tail_call(proc, closure)?;
+            }
+            CALL_HOST => {
+                let insn = CallHost::read(&mut self.operands());
+                self.call_host(insn.host)?;
+            }
+            BRANCH => {
+                let insn = Branch::read(&mut self.operands());
+                self.branch_rel(insn.offset);
+            }
+            BRANCH_Z => {
+                let insn = BranchZ::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? == 0 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_NZ => {
+                let insn = BranchNz::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? != 0 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LZ => {
+                let insn = BranchLz::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? < 0 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LEZ => {
+                let insn = BranchLez::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? <= 0 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GZ => {
+                let insn = BranchGz::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? > 0 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GEZ => {
+                let insn = BranchGez::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? >= 0 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_EQ => {
+                let insn = BranchEq::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                if left == right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_NE => {
+                let insn = BranchNe::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                if left != right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LT => {
+                let insn = BranchLt::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                if left < right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LE => {
+                let insn = BranchLe::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                if left <= right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GT => {
+                let insn = BranchGt::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                if left > right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GE => {
+                let insn = BranchGe::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                if left >= right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LTU => {
+                let insn = BranchLtu::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                if left < right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LEU => {
+                let insn = BranchLeu::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                if left <= right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GTU => {
+                let insn = BranchGtu::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                if left > right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GEU => {
+                let insn = BranchGeu::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                if left >= right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_EQI => {
+                let insn = BranchEqi::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? == insn.value as i64 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_NEI => {
+                let insn = BranchNei::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? != insn.value as i64 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LTI => {
+                let insn = BranchLti::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? < insn.value as i64 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LEI => {
+                let insn = BranchLei::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? <= insn.value as i64 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GTI => {
+                let insn = BranchGti::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? > insn.value as i64 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GEI => {
+                let insn = BranchGei::read(&mut self.operands());
+                if self.read_slot_s64(insn.src)? >= insn.value as i64 {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_EQF => {
+                let insn = BranchEqf::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                if left == right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_NEF => {
+                let insn = BranchNef::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                if left != right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LTF => {
+                let insn = BranchLtf::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                if left < right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_LEF => {
+                let insn = BranchLef::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                if left <= right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GTF => {
+                let insn = BranchGtf::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                if left > right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_GEF => {
+                let insn = BranchGef::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                if left >= right {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_UNOF => {
+                let insn = BranchUnof::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                if left.is_nan() || right.is_nan() {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            BRANCH_NANF => {
+                let insn = BranchNanf::read(&mut self.operands());
+                if self.read_slot_f64(insn.src)?.is_nan() {
+                    self.branch_rel(insn.offset);
+                }
+            }
+            RETURN => {
+                let ra = self.stack.return_call()?;
+                self.pc = ra;
+            }
+            THROW => {
+                let insn = Throw::read(&mut self.operands());
+                let value = self.read_slot(insn.src)?;
+                return Err(TrapKind::Thrown(value).into());
+            }
+            ADD_S64 => {
+                let insn = AddS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                let value = left.checked_add(right).ok_or(TrapKind::IntegerOverflow)?;
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            SUB_S64 => {
+                let insn = SubS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                let value = left.checked_sub(right).ok_or(TrapKind::IntegerOverflow)?;
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            MUL_S64 => {
+                let insn = MulS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                let value = left.checked_mul(right).ok_or(TrapKind::IntegerOverflow)?;
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            DIV_S64 => {
+                let insn = DivS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                if right == 0 {
+                    return Err(TrapKind::DivideByZero.into());
+                }
+                let value = left.checked_div(right).ok_or(TrapKind::IntegerOverflow)?;
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            REM_S64 => {
+                let insn = RemS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                if right == 0 {
+                    return Err(TrapKind::DivideByZero.into());
+                }
+                let value = left.checked_rem(right).ok_or(TrapKind::IntegerOverflow)?;
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            ADD_S64_WRAP => {
+                let insn = AddS64Wrap::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left.wrapping_add(right)))?;
+            }
+            SUB_S64_WRAP => {
+                let insn = SubS64Wrap::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left.wrapping_sub(right)))?;
+            }
+            MUL_S64_WRAP => {
+                let insn = MulS64Wrap::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left.wrapping_mul(right)))?;
+            }
+            ADD_S64_SAT => {
+                let insn = AddS64Sat::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left.saturating_add(right)))?;
+            }
+            SUB_S64_SAT => {
+                let insn = SubS64Sat::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left.saturating_sub(right)))?;
+            }
+            MUL_S64_SAT => {
+                let insn = MulS64Sat::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left.saturating_mul(right)))?;
+            }
+            MULH_S64 => {
+                let insn = MulhS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as i128;
+                let right = self.read_slot_s64(insn.right)? as i128;
+                self.write_slot(insn.dst, value!(@s64 ((left * right) >> 64) as i64))?;
+            }
+            DIV_U64 => {
+                let insn = DivU64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                if right == 0 {
+                    return Err(TrapKind::DivideByZero.into());
+                }
+                self.write_slot(insn.dst, value!(@s64 (left / right) as i64))?;
+            }
+            REM_U64 => {
+                let insn = RemU64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                if right == 0 {
+                    return Err(TrapKind::DivideByZero.into());
+                }
+                self.write_slot(insn.dst, value!(@s64 (left % right) as i64))?;
+            }
+            MULH_U64 => {
+                let insn = MulhU64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64 as u128;
+                let right = self.read_slot_s64(insn.right)? as u64 as u128;
+                self.write_slot(insn.dst, value!(@s64 ((left * right) >> 64) as i64))?;
+            }
+            ADD_S64_IMM => {
+                let insn = AddS64Imm::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.src)?;
+                let value = left
+                    .checked_add(insn.value as i64)
+                    .ok_or(TrapKind::IntegerOverflow)?;
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            SUB_S64_IMM => {
+                let insn = SubS64Imm::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.src)?;
+                let value = left
+                    .checked_sub(insn.value as i64)
+                    .ok_or(TrapKind::IntegerOverflow)?;
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            MUL_S64_IMM => {
+                let insn = MulS64Imm::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.src)?;
+                let value = left
+                    .checked_mul(insn.value as i64)
+                    .ok_or(TrapKind::IntegerOverflow)?;
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            ADD_F64 => {
+                let insn = AddF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@f64 left + right))?;
+            }
+            SUB_F64 => {
+                let insn = SubF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@f64 left - right))?;
+            }
+            MUL_F64 => {
+                let insn = MulF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@f64 left * right))?;
+            }
+            DIV_F64 => {
+                let insn = DivF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@f64 left / right))?;
+            }
+            REM_F64 => {
+                let insn = RemF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@f64 left % right))?;
+            }
+            AND_S64 => {
+                let insn = AndS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left & right))?;
+            }
+            OR_S64 => {
+                let insn = OrS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left | right))?;
+            }
+            XOR_S64 => {
+                let insn = XorS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left ^ right))?;
+            }
+            NOT_S64 => {
+                let insn = NotS64::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 !value))?;
+            }
+            SHL_S64 => {
+                let insn = ShlS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                let value = if right < 64 { left << right } else { 0 };
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            SHR_U64 => {
+                let insn = ShrU64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                let value = if right < 64 {
+                    (left as u64) >> right
+                } else {
+                    0
+                };
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            SHR_S64 => {
+                let insn = ShrS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                // Shifting by 63 or more leaves only copies of the sign bit
+                self.write_slot(insn.dst, value!(@s64 left >> right.min(63)))?;
+            }
+            ROTL_S64 => {
+                let insn = RotlS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 left.rotate_left((right & 63) as u32)))?;
+            }
+            ROTR_S64 => {
+                let insn = RotrS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(
+                    insn.dst,
+                    value!(@s64 left.rotate_right((right & 63) as u32)),
+                )?;
+            }
+            POPCNT_S64 => {
+                let insn = PopcntS64::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value.count_ones() as i64))?;
+            }
+            CLZ_S64 => {
+                let insn = ClzS64::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value.leading_zeros() as i64))?;
+            }
+            CTZ_S64 => {
+                let insn = CtzS64::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value.trailing_zeros() as i64))?;
+            }
+            EQ_S64 => {
+                let insn = EqS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left == right) as i64))?;
+            }
+            NE_S64 => {
+                let insn = NeS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left != right) as i64))?;
+            }
+            LT_S64 => {
+                let insn = LtS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left < right) as i64))?;
+            }
+            LE_S64 => {
+                let insn = LeS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left <= right) as i64))?;
+            }
+            GT_S64 => {
+                let insn = GtS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left > right) as i64))?;
+            }
+            GE_S64 => {
+                let insn = GeS64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)?;
+                let right = self.read_slot_s64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left >= right) as i64))?;
+            }
+            LT_U64 => {
+                let insn = LtU64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                self.write_slot(insn.dst, value!(@s64 (left < right) as i64))?;
+            }
+            LE_U64 => {
+                let insn = LeU64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                self.write_slot(insn.dst, value!(@s64 (left <= right) as i64))?;
+            }
+            GT_U64 => {
+                let insn = GtU64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                self.write_slot(insn.dst, value!(@s64 (left > right) as i64))?;
+            }
+            GE_U64 => {
+                let insn = GeU64::read(&mut self.operands());
+                let left = self.read_slot_s64(insn.left)? as u64;
+                let right = self.read_slot_s64(insn.right)? as u64;
+                self.write_slot(insn.dst, value!(@s64 (left >= right) as i64))?;
+            }
+            EQ_F64 => {
+                let insn = EqF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left == right) as i64))?;
+            }
+            NE_F64 => {
+                let insn = NeF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left != right) as i64))?;
+            }
+            LT_F64 => {
+                let insn = LtF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left < right) as i64))?;
+            }
+            LE_F64 => {
+                let insn = LeF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left <= right) as i64))?;
+            }
+            GT_F64 => {
+                let insn = GtF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left > right) as i64))?;
+            }
+            GE_F64 => {
+                let insn = GeF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(insn.dst, value!(@s64 (left >= right) as i64))?;
+            }
+            UNO_F64 => {
+                let insn = UnoF64::read(&mut self.operands());
+                let left = self.read_slot_f64(insn.left)?;
+                let right = self.read_slot_f64(insn.right)?;
+                self.write_slot(
+                    insn.dst,
+                    value!(@s64 (left.is_nan() || right.is_nan()) as i64),
+                )?;
+            }
+            IS_NAN_F64 => {
+                let insn = IsNanF64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?.is_nan();
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            S64_TO_F64 => {
+                let insn = S64ToF64::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@f64 value as f64))?;
+            }
+            F64_TO_S64 => {
+                let insn = F64ToS64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                // The remainder is exact and its low 64 bits are the wrapped value
+                let value = if value.is_finite() {
+                    (value.trunc() % 18446744073709551616.0) as i128 as i64
+                } else {
+                    0
+                };
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            F64_SAT_S64 => {
+                let insn = F64SatS64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            F64_CHK_S64 => {
+                let insn = F64ChkS64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?.trunc();
+                // -2^63 is exact, the upper bound 2^63 is exclusive
+                if !(value >= i64::MIN as f64 && value < -(i64::MIN as f64)) {
+                    return Err(TrapKind::InvalidConversion.into());
+                }
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            FLOOR_F64 => {
+                let insn = FloorF64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                self.write_slot(insn.dst, value!(@f64 value.floor()))?;
+            }
+            CEIL_F64 => {
+                let insn = CeilF64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                self.write_slot(insn.dst, value!(@f64 value.ceil()))?;
+            }
+            ROUND_F64 => {
+                let insn = RoundF64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                self.write_slot(insn.dst, value!(@f64 value.round()))?;
+            }
+            TRUNC_F64 => {
+                let insn = TruncF64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                self.write_slot(insn.dst, value!(@f64 value.trunc()))?;
+            }
+            SIGN_EXT8 => {
+                let insn = SignExt8::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value as i8 as i64))?;
+            }
+            SIGN_EXT16 => {
+                let insn = SignExt16::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value as i16 as i64))?;
+            }
+            SIGN_EXT32 => {
+                let insn = SignExt32::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value as i32 as i64))?;
+            }
+            ZERO_EXT8 => {
+                let insn = ZeroExt8::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value as u8 as i64))?;
+            }
+            ZERO_EXT16 => {
+                let insn = ZeroExt16::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value as u16 as i64))?;
+            }
+            ZERO_EXT32 => {
+                let insn = ZeroExt32::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value as u32 as i64))?;
+            }
+            F64_TO_BITS => {
+                let insn = F64ToBits::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                self.write_slot(insn.dst, value!(@s64 value.to_bits() as i64))?;
+            }
+            BITS_TO_F64 => {
+                let insn = BitsToF64::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_slot(insn.dst, value!(@f64 f64::from_bits(value as u64)))?;
+            }
+            NEW_RECORD => {
+                let insn = NewRecord::read(&mut self.operands());
+                let fields = vec![value!(@s64 0); insn.size as usize];
+                let object = self.alloc_object(Object::Record(fields.into()))?;
+                self.write_slot(insn.dst, value!(@ref object))?;
+            }
+            NEW_ARRAY => {
+                let insn = NewArray::read(&mut self.operands());
+                let len = self.read_slot_s64(insn.len)?;
+                let Ok(len) = usize::try_from(len) else {
+                    return Err(TrapKind::BadLength(len).into());
+                };
+                // Check the size before allocating the elements
+                self.reserve_heap(len.saturating_add(1))?;
+                let elements = vec![value!(@s64 0); len];
+                let object = self.heap.alloc(Object::Array(elements.into()));
+                self.write_slot(insn.dst, value!(@ref object))?;
+            }
+            LOAD_FIELD => {
+                let insn = LoadField::read(&mut self.operands());
+                let object = self.read_slot_ref(insn.src)?;
+                let value = self.load_field(object, insn.field as i64)?;
+                self.write_slot(insn.dst, value)?;
+            }
+            STORE_FIELD => {
+                let insn = StoreField::read(&mut self.operands());
+                let object = self.read_slot_ref(insn.dst)?;
+                let value = self.read_slot(insn.src)?;
+                self.store_field(object, insn.field as i64, value)?;
+            }
+            LOAD_ELEM => {
+                let insn = LoadElem::read(&mut self.operands());
+                let object = self.read_slot_ref(insn.src)?;
+                let index = self.read_slot_s64(insn.index)?;
+                let value = self.load_field(object, index)?;
+                self.write_slot(insn.dst, value)?;
+            }
+            STORE_ELEM => {
+                let insn = StoreElem::read(&mut self.operands());
+                let object = self.read_slot_ref(insn.dst)?;
+                let index = self.read_slot_s64(insn.index)?;
+                let value = self.read_slot(insn.src)?;
+                self.store_field(object, index, value)?;
+            }
+            ARRAY_LEN => {
+                let insn = ArrayLen::read(&mut self.operands());
+                let object = self.read_slot_ref(insn.src)?;
+                let len = self.object(object)?.fields().len();
+                self.write_slot(insn.dst, value!(@s64 len as i64))?;
+            }
+            STR_LEN => {
+                let insn = StrLen::read(&mut self.operands());
+                let str = self.read_slot_str(insn.src)?;
+                let len = self.str(str)?.len();
+                self.write_slot(insn.dst, value!(@s64 len as i64))?;
+            }
+            STR_CHARS => {
+                let insn = StrChars::read(&mut self.operands());
+                let str = self.read_slot_str(insn.src)?;
+                let chars = self.str(str)?.chars().count();
+                self.write_slot(insn.dst, value!(@s64 chars as i64))?;
+            }
+            STR_CONCAT => {
+                let insn = StrConcat::read(&mut self.operands());
+                let left = self.read_slot_str(insn.left)?;
+                let right = self.read_slot_str(insn.right)?;
+                let len = self.str(left)?.len().checked_add(self.str(right)?.len());
+                let Some(len) = len else {
+                    return Err(TrapKind::OutOfMemory.into());
+                };
+                // Check the size before building the string
+                self.reserve_heap(Object::str_size(len))?;
+                let value = [self.str(left)?, self.str(right)?].concat();
+                let str = self.heap.alloc(Object::Str(value.into()));
+                self.write_slot(insn.dst, value!(@str str))?;
+            }
+            STR_SUB => {
+                let insn = StrSub::read(&mut self.operands());
+                let str = self.read_slot_str(insn.src)?;
+                let start = self.read_slot_s64(insn.start)?;
+                let end = self.read_slot_s64(insn.end)?;
+                let value = self.str(str)?;
+                let range = char_boundary(value, start)?..char_boundary(value, end)?;
+                if range.start > range.end {
+                    return Err(TrapKind::BadRange { start, end }.into());
+                }
+                let value = value[range].to_owned();
+                let str = self.alloc_str(value)?;
+                self.write_slot(insn.dst, value!(@str str))?;
+            }
+            STR_CMP => {
+                let insn = StrCmp::read(&mut self.operands());
+                let left = self.read_slot_str(insn.left)?;
+                let right = self.read_slot_str(insn.right)?;
+                let ordering = self.str(left)?.cmp(self.str(right)?);
+                self.write_slot(insn.dst, value!(@s64 ordering as i64))?;
+            }
+            STR_BYTE => {
+                let insn = StrByte::read(&mut self.operands());
+                let str = self.read_slot_str(insn.src)?;
+                let index = self.read_slot_s64(insn.index)?;
+                let bytes = self.str(str)?.as_bytes();
+                let byte = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| bytes.get(index))
+                    .ok_or_else(|| out_of_bounds(index, bytes.len()))?;
+                self.write_slot(insn.dst, value!(@s64 *byte as i64))?;
+            }
+            STR_CHAR => {
+                let insn = StrChar::read(&mut self.operands());
+                let str = self.read_slot_str(insn.src)?;
+                let index = self.read_slot_s64(insn.index)?;
+                let value = self.str(str)?;
+                let Some(c) = value[char_boundary(value, index)?..].chars().next() else {
+                    return Err(out_of_bounds(index, value.len()));
+                };
+                self.write_slot(insn.dst, value!(@s64 c as i64))?;
+            }
+            S64_TO_STR => {
+                let insn = S64ToStr::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                let str = self.alloc_str(value.to_string())?;
+                self.write_slot(insn.dst, value!(@str str))?;
+            }
+            F64_TO_STR => {
+                let insn = F64ToStr::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                let str = self.alloc_str(value.to_string())?;
+                self.write_slot(insn.dst, value!(@str str))?;
+            }
+            STR_TO_S64 => {
+                let insn = StrToS64::read(&mut self.operands());
+                let str = self.read_slot_str(insn.src)?;
+                let value = self.str(str)?;
+                let Ok(value) = value.parse::<i64>() else {
+                    return Err(TrapKind::InvalidNumber(value.to_owned()).into());
+                };
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            STR_TO_F64 => {
+                let insn = StrToF64::read(&mut self.operands());
+                let str = self.read_slot_str(insn.src)?;
+                let value = self.str(str)?;
+                let Ok(value) = value.parse::<f64>() else {
+                    return Err(TrapKind::InvalidNumber(value.to_owned()).into());
+                };
+                self.write_slot(insn.dst, value!(@f64 value))?;
+            }
+            LOAD_U8 => {
+                let insn = LoadU8::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = u8::from_le_bytes(self.read_memory(addr)?);
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            LOAD_S8 => {
+                let insn = LoadS8::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = i8::from_le_bytes(self.read_memory(addr)?);
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            LOAD_U16 => {
+                let insn = LoadU16::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = u16::from_le_bytes(self.read_memory(addr)?);
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            LOAD_S16 => {
+                let insn = LoadS16::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = i16::from_le_bytes(self.read_memory(addr)?);
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            LOAD_U32 => {
+                let insn = LoadU32::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = u32::from_le_bytes(self.read_memory(addr)?);
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            LOAD_S32 => {
+                let insn = LoadS32::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = i32::from_le_bytes(self.read_memory(addr)?);
+                self.write_slot(insn.dst, value!(@s64 value as i64))?;
+            }
+            LOAD_64 => {
+                let insn = Load64::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = i64::from_le_bytes(self.read_memory(addr)?);
+                self.write_slot(insn.dst, value!(@s64 value))?;
+            }
+            LOAD_F64 => {
+                let insn = LoadF64::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = f64::from_le_bytes(self.read_memory(addr)?);
+                self.write_slot(insn.dst, value!(@f64 value))?;
+            }
+            STORE_8 => {
+                let insn = Store8::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_memory(addr, (value as u8).to_le_bytes())?;
+            }
+            STORE_16 => {
+                let insn = Store16::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_memory(addr, (value as u16).to_le_bytes())?;
+            }
+            STORE_32 => {
+                let insn = Store32::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_memory(addr, (value as u32).to_le_bytes())?;
+            }
+            STORE_64 => {
+                let insn = Store64::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = self.read_slot_s64(insn.src)?;
+                self.write_memory(addr, value.to_le_bytes())?;
+            }
+            STORE_F64 => {
+                let insn = StoreF64::read(&mut self.operands());
+                let addr = self.read_slot_s64(insn.addr)?;
+                let value = self.read_slot_f64(insn.src)?;
+                self.write_memory(addr, value.to_le_bytes())?;
+            }
+            MEMORY_SIZE => {
+                let insn = MemorySize::read(&mut self.operands());
+                let size = self.memory.size();
+                self.write_slot(insn.dst, value!(@s64 size as i64))?;
+            }
+            PRINT_S64 => {
+                let insn = PrintS64::read(&mut self.operands());
+                let value = self.read_slot_s64(insn.src)?;
+                println!("{value}");
+            }
+            PRINT_F64 => {
+                let insn = PrintF64::read(&mut self.operands());
+                let value = self.read_slot_f64(insn.src)?;
+                println!("{value}");
+            }
+            PRINT_PROC => {
+                let insn = PrintProc::read(&mut self.operands());
+                match self.read_slot(insn.src)? {
+                    Value::Proc(index) => println!("{}", value!(@proc index)),
+                    Value::Closure(closure) => println!("{}", self.object(closure)?),
+                    value => return Err(type_mismatch(insn.src, Tag::Proc, value)),
+                }
+            }
+            PRINT_REF => {
+                let insn = PrintRef::read(&mut self.operands());
+                let object = self.read_slot_ref(insn.src)?;
+                println!("{}", self.object(object)?);
+            }
+            PRINT_STR => {
+                let insn = PrintStr::read(&mut self.operands());
+                let str = self.read_slot_str(insn.src)?;
+                println!("{}", self.str(str)?);
+            }
+            HALT => self.halt(),
+            BREAKPOINT => (),
+            _ => return Err(TrapKind::InvalidOpcode(opcode).into()),
+        }
+        Ok(())
+    }
+
+    /// Runs until the program halts or traps
+    pub fn run(&mut self) -> Result<(), Trap> {
+        while !self.pc.is_null() {
+            let opcode = self.fetch();
+            self.execute(opcode)?;
+        }
+        Ok(())
+    }
+
+    pub fn run_debug(&mut self) -> Result<(), Trap> {
+        while !self.pc.is_null() {
+            let opcode = self.fetch();
+            self.execute(opcode)?;
+        }
+        Ok(())
+    }
+}
+
+#[inline]
+fn expect_s64(slot: i16, value: Value) -> Result<i64, Trap> {
+    match value {
+        Value::S64(value) => Ok(value),
+        value => Err(type_mismatch(slot, Tag::S64, value)),
+    }
+}
+
+#[inline]
+fn expect_f64(slot: i16, value: Value) -> Result<f64, Trap> {
+    match value {
+        Value::F64(value) => Ok(value),
+        value => Err(type_mismatch(slot, Tag::F64, value)),
+    }
+}
+
+#[inline]
+fn expect_ref(slot: i16, value: Value) -> Result<u32, Trap> {
+    match value {
+        Value::Ref(index) => Ok(index),
+        value => Err(type_mismatch(slot, Tag::Ref, value)),
+    }
+}
+
+#[inline]
+fn expect_str(slot: i16, value: Value) -> Result<u32, Trap> {
+    match value {
+        Value::Str(index) => Ok(index),
+        value => Err(type_mismatch(slot, Tag::Str, value)),
+    }
+}
+
+#[inline]
+fn expect_proc(slot: i16, value: Value) -> Result<u32, Trap> {
+    match value {
+        Value::Proc(index) => Ok(index),
+        value => Err(type_mismatch(slot, Tag::Proc, value)),
+    }
+}
+
+#[cold]
+fn type_mismatch(slot: i16, expected: Tag, found: Value) -> Trap {
+    TrapKind::TypeMismatch {
+        slot,
+        expected,
+        found: found.tag(),
+    }
+    .into()
+}
+
+#[cold]
+fn bad_address(addr: i64, size: usize) -> Trap {
+    TrapKind::BadAddress { addr, size }.into()
+}
+
+#[cold]
+fn out_of_bounds(index: i64, len: usize) -> Trap {
+    TrapKind::IndexOutOfBounds { index, len }.into()
+}
+
+/// Checks that `index` is a char boundary of `str` and converts it to `usize`
+fn char_boundary(str: &str, index: i64) -> Result<usize, Trap> {
+    match usize::try_from(index) {
+        Ok(boundary) if str.is_char_boundary(boundary) => Ok(boundary),
+        Ok(boundary) if boundary < str.len() => Err(TrapKind::NotCharBoundary(index).into()),
+        _ => Err(out_of_bounds(index, str.len())),
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads the operands of the instruction at the program counter
+///
+/// Only verified procs are executed, so reads stay inside the code of the current proc.
+pub(crate) struct Operands<'a>(&'a mut *const u8);
+
+impl Read for Operands<'_> {
+    #[inline]
+    fn read<T: Copy>(&mut self) -> T {
+        unsafe {
+            let value = (*self.0 as *const T).read_unaligned();
+            *self.0 = self.0.add(size_of::<T>());
+            value
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constant {
+    S64(i64),
+    F64(f64),
+    /// Loaded as a [Value::Str] that is allocated once and never collected
+    Str(String),
+}
+
+impl From<i64> for Constant {
+    fn from(value: i64) -> Self {
+        Self::S64(value)
+    }
+}
+
+impl From<f64> for Constant {
+    fn from(value: f64) -> Self {
+        Self::F64(value)
+    }
+}
+
+impl From<&str> for Constant {
+    fn from(value: &str) -> Self {
+        Self::Str(value.to_owned())
+    }
+}
+
+impl From<String> for Constant {
+    fn from(value: String) -> Self {
+        Self::Str(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+
+    use super::*;
+
+    /// Assembles `src` into a runtime
+    fn runtime(src: &str) -> Runtime {
+        runtime_with(src, RuntimeBuilder::new())
+    }
+
+    fn runtime_with(src: &str, builder: RuntimeBuilder) -> Runtime {
+        assemble(src).unwrap().into_runtime_with(builder).unwrap()
+    }
+
+    /// Invokes the proc `name` of `runtime` with s64 arguments
+    fn invoke(runtime: &mut Runtime, name: &str, args: &[i64]) -> Result<Vec<Value>, TrapKind> {
+        let proc = runtime.find_proc(name).unwrap();
+        let args: Vec<_> = args.iter().map(|&arg| Value::S64(arg)).collect();
+        runtime.invoke(proc, &args).map_err(|trap| trap.kind)
+    }
+
+    /// Invokes the proc `name` and returns the s64 in its first argument slot
+    fn invoke_s64(runtime: &mut Runtime, name: &str, args: &[i64]) -> Result<i64, TrapKind> {
+        match invoke(runtime, name, args)?[0] {
+            Value::S64(value) => Ok(value),
+            value => panic!("expected an s64, found {value}"),
+        }
+    }
+
+    /// Runs the proc `name` of `runtime` and returns its trap
+    fn run(runtime: &mut Runtime, name: &str) -> Result<(), TrapKind> {
+        let proc = runtime.find_proc(name).unwrap();
+        runtime.call(proc).map_err(|trap| trap.kind)?;
+        runtime.run().map_err(|trap| trap.kind)
+    }
+
+    #[test]
+    fn host_reset() {
+        let src = "
+.proc main
+    hcall stop
+    hlt
+.end
+";
+        let mut runtime = runtime(src);
+        runtime.register_host("stop", |rt| {
+            rt.reset();
+            Ok(())
+        });
+        assert_eq!(run(&mut runtime, "main"), Err(TrapKind::HostFrame));
+        assert_eq!(runtime.stack.depth(), 0);
+        // The runtime is usable again after a reset
+        runtime.register_host("stop", |_| Ok(()));
+        runtime.reset();
+        assert_eq!(run(&mut runtime, "main"), Ok(()));
+    }
+
+    /// Catches values thrown by `thrower` in `main`, with an uncaught thrower for comparison
+    const UNWIND: &str = "
+.proc main
+    alloc 1
+    .catch try, done, failed, 0
+try:
+    call outer
+done:
+    movv -1, 0
+    ret
+failed:
+    mov -1, 0
+    ret
+.end
+
+.proc outer
+    alloc 2
+    movv 1, 1
+    call thrower
+    ret
+.end
+
+.proc thrower
+    alloc 1
+    movv 0, 42
+    throw 0
+.end
+
+; The region starts after the call, so the return address is outside of it
+.proc after
+    alloc 1
+    .catch start, end, failed, 0
+    call thrower
+start:
+    movv 0, 0
+end:
+    ret
+failed:
+    movv -1, 1
+    ret
+.end
+";
+
+    #[test]
+    fn unwind_frames() {
+        let mut runtime = runtime(UNWIND);
+        assert_eq!(invoke(&mut runtime, "main", &[7]), Ok(vec![Value::S64(42)]));
+        assert_eq!(runtime.stack.depth(), 0);
+        assert_eq!(runtime.thrown, None);
+    }
+
+    #[test]
+    fn unwind_return_address() {
+        let mut runtime = runtime(UNWIND);
+        assert_eq!(
+            invoke(&mut runtime, "after", &[7]),
+            Err(TrapKind::Uncaught("42".into()))
+        );
+    }
+
+    #[test]
+    fn innermost_handler() {
+        let src = "
+.proc main
+    alloc 1
+    .catch inner_try, inner_done, inner, 0
+    .catch outer_try, outer_done, outer, 0
+outer_try:
+    movv 0, 1
+inner_try:
+    throw 0
+inner_done:
+    movv 0, 3
+    throw 0
+outer_done:
+    ret
+inner:
+    movv -1, 2
+    ret
+outer:
+    movv -1, 3
+    ret
+.end
+
+; The handler of the callee comes before the one of the caller
+.proc caller
+    alloc 1
+    .catch try, done, failed, 0
+try:
+    call main
+done:
+    mov -1, 0
+    ret
+failed:
+    movv -1, 4
+    ret
+.end
+";
+        let mut runtime = runtime(src);
+        assert_eq!(invoke(&mut runtime, "main", &[0]), Ok(vec![Value::S64(2)]));
+        assert_eq!(
+            invoke(&mut runtime, "caller", &[0]),
+            Ok(vec![Value::S64(2)])
+        );
+    }
+
+    #[test]
+    fn catch_traps() {
+        let src = "
+.proc main
+    alloc 2
+    .catch try, done, failed, 0
+try:
+    movv 1, 0
+    divs -1, -1, 1
+done:
+    ret
+failed:
+    mov -1, 0
+    ret
+.end
+";
+        let mut runtime = runtime(src);
+        assert_eq!(
+            invoke(&mut runtime, "main", &[1]),
+            Err(TrapKind::DivideByZero)
+        );
+
+        let mut runtime = runtime_with(src, RuntimeBuilder::new().catch_traps(true));
+        let result = invoke(&mut runtime, "main", &[1]).unwrap();
+        let Value::Str(str) = result[0] else {
+            panic!("expected a string, found {result:?}");
+        };
+        assert_eq!(runtime.str(str), Ok("division by zero"));
+    }
+
+    #[test]
+    fn uncaught() {
+        let mut runtime = runtime(UNWIND);
+        let thrower = runtime.find_proc("thrower").unwrap();
+        runtime.call(thrower).unwrap();
+        let trap = runtime.run().unwrap_err();
+        assert_eq!(trap.kind, TrapKind::Uncaught("42".into()));
+        assert_eq!(runtime.thrown, None);
+    }
+
+    #[test]
+    fn throw_through_host() {
+        let src = "
+.proc main
+    alloc 1
+    .catch try, done, failed, 0
+try:
+    hcall nested
+done:
+    movv -1, 0
+    ret
+failed:
+    mov -1, 0
+    ret
+.end
+
+.proc thrower
+    alloc 1
+    movv 0, 99
+    throw 0
+.end
+";
+        let mut runtime = runtime(src);
+        runtime.register_host("nested", |rt| {
+            let thrower = rt.find_proc("thrower").unwrap();
+            rt.invoke(thrower, &[]).map(|_| ())
+        });
+        assert_eq!(invoke(&mut runtime, "main", &[0]), Ok(vec![Value::S64(99)]));
+        assert_eq!(runtime.thrown, None);
+
+        // A swallowed value is not kept alive
+        runtime.register_host("nested", |rt| {
+            let thrower = rt.find_proc("thrower").unwrap();
+            assert!(rt.invoke(thrower, &[]).is_err());
+            assert!(rt.thrown.is_some());
+            Ok(())
+        });
+        assert_eq!(invoke(&mut runtime, "main", &[0]), Ok(vec![Value::S64(0)]));
+        assert_eq!(runtime.thrown, None);
+    }
+
+    /// Procs for the [Runtime::invoke] tests
+    const INVOKE: &str = "
+.proc square
+    muls -1, -1, -1
+    ret
+.end
+
+.proc divide
+    divs -1, -1, -2
+    ret
+.end
+
+.proc stop
+    hlt
+.end
+
+; Squares the argument in a host function
+.proc host_square
+    alloc 1
+    mov 0, -1
+    hcall square
+    mov -1, 0
+    ret
+.end
+";
+
+    #[test]
+    fn invoke_after_trap() {
+        let mut runtime = runtime(INVOKE);
+        assert_eq!(
+            invoke(&mut runtime, "divide", &[7, 2]),
+            Ok(vec![Value::S64(3), Value::S64(2)])
+        );
+        assert_eq!(
+            invoke(&mut runtime, "divide", &[7, 0]),
+            Err(TrapKind::DivideByZero)
+        );
+        assert_eq!(runtime.stack.depth(), 0);
+        assert_eq!(
+            invoke(&mut runtime, "square", &[-5]),
+            Ok(vec![Value::S64(25)])
+        );
+    }
+
+    #[test]
+    fn invoke_from_host() {
+        let mut runtime = runtime(INVOKE);
+        runtime.register_host("square", |rt| {
+            let square = rt.find_proc("square").unwrap();
+            let value = rt.load_slot(-1)?;
+            let result = rt.invoke(square, &[value])?;
+            rt.store_slot(-1, result[0])
+        });
+        assert_eq!(
+            invoke(&mut runtime, "host_square", &[9]),
+            Ok(vec![Value::S64(81)])
+        );
+        assert_eq!(runtime.stack.depth(), 0);
+    }
+
+    #[test]
+    fn invoke_halted() {
+        let mut runtime = runtime(INVOKE);
+        assert_eq!(invoke(&mut runtime, "stop", &[1]), Err(TrapKind::Halted));
+        assert_eq!(runtime.stack.depth(), 0);
+        assert_eq!(
+            invoke(&mut runtime, "square", &[3]),
+            Ok(vec![Value::S64(9)])
+        );
+    }
+
+    #[test]
+    fn tail_call_constant_stack() {
+        let src = "
+; Counts the arguments down to 0 and up from the start
+.proc count
+    bz -1, done
+    subsi -1, -1, 1
+    addsi -2, -2, 1
+    tcall count
+done:
+    ret
+.end
+";
+        let mut runtime = runtime_with(src, RuntimeBuilder::new().stack_size(64));
+        assert_eq!(
+            invoke(&mut runtime, "count", &[100000, 0]),
+            Ok(vec![Value::S64(0), Value::S64(100000)])
+        );
+    }
+
+    #[test]
+    fn tail_call_clears_closure() {
+        let src = "
+.proc main
+    alloc 2
+    movv 0, 5
+    closure 1, captured, 0, 1
+    call_dyn 1
+    ret
+.end
+
+.proc captured
+    alloc 1
+    ldcap 0, 0
+    tcall plain
+.end
+
+.proc plain
+    alloc 1
+    ldcap 0, 0
+    ret
+.end
+";
+        let mut runtime = runtime(src);
+        assert_eq!(invoke(&mut runtime, "main", &[]), Err(TrapKind::NoClosure));
+    }
+
+    #[test]
+    fn closure_captures() {
+        let src = "
+; Returns a + b * c with b and c captured
+.proc main
+    alloc 4
+    movv 0, 3
+    movv 1, 4
+    closure 3, madd, 0, 2
+    mov 2, -1
+    call_dyn 3
+    mov -1, 2
+    ret
+.end
+
+; The closure is passed as -1 and a as -2
+.proc madd
+    alloc 2
+    ldcap 0, 0
+    ldcap 1, 1
+    muls 0, 0, 1
+    adds -2, -2, 0
+    ret
+.end
+";
+        let mut runtime = runtime(src);
+        assert_eq!(invoke(&mut runtime, "main", &[5]), Ok(vec![Value::S64(17)]));
+    }
+
+    #[test]
+    fn closure_survives_gc() {
+        let src = "
+.proc main
+    alloc 2
+    movv 0, 42
+    closure 1, callee, 0, 1
+    call_dyn 1
+    mov -1, 0
+    ret
+.end
+
+; The closure is only held by the frame once its slot is overwritten
+.proc callee
+    alloc 1
+    movv -1, 0
+    hcall gc
+    ldcap 0, 0
+    mov -2, 0
+    ret
+.end
+";
+        let mut runtime = runtime(src);
+        runtime.register_host("gc", |rt| {
+            assert_eq!(rt.collect_garbage(), 0);
+            Ok(())
+        });
+        assert_eq!(invoke(&mut runtime, "main", &[0]), Ok(vec![Value::S64(42)]));
+        assert_eq!(runtime.collect_garbage(), 1);
+    }
+
+    #[test]
+    fn str_bounds() {
+        let src = r#"
+; Bytes: a, two for é, three for €, b
+.const text "aé€b"
+
+.proc sub
+    alloc 2
+    ldc 0, text
+    ssub 1, 0, -1, -2
+    slen -1, 1
+    ret
+.end
+
+.proc byte
+    alloc 1
+    ldc 0, text
+    sbyte -1, 0, -1
+    ret
+.end
+
+.proc char
+    alloc 1
+    ldc 0, text
+    schar -1, 0, -1
+    ret
+.end
+"#;
+        let mut runtime = runtime(src);
+        let out_of_bounds = |index| Err(TrapKind::IndexOutOfBounds { index, len: 7 });
+        let cases: &[(&str, &[i64], Result<i64, TrapKind>)] = &[
+            ("sub", &[1, 3], Ok(2)),
+            ("sub", &[0, 7], Ok(7)),
+            ("sub", &[7, 7], Ok(0)),
+            ("sub", &[2, 3], Err(TrapKind::NotCharBoundary(2))),
+            ("sub", &[1, 5], Err(TrapKind::NotCharBoundary(5))),
+            ("sub", &[0, 8], out_of_bounds(8)),
+            ("sub", &[-1, 3], out_of_bounds(-1)),
+            ("sub", &[3, 1], Err(TrapKind::BadRange { start: 3, end: 1 })),
+            ("byte", &[6], Ok(b'b' as i64)),
+            ("byte", &[2], Ok(0xa9)),
+            ("byte", &[7], out_of_bounds(7)),
+            ("byte", &[-1], out_of_bounds(-1)),
+            ("char", &[1], Ok('é' as i64)),
+            ("char", &[3], Ok('€' as i64)),
+            ("char", &[4], Err(TrapKind::NotCharBoundary(4))),
+            ("char", &[7], out_of_bounds(7)),
+            ("char", &[i64::MIN], out_of_bounds(i64::MIN)),
+        ];
+        for (proc, args, expected) in cases {
+            assert_eq!(
+                invoke_s64(&mut runtime, proc, args),
+                *expected,
+                "{proc} {args:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn slot_and_memory_bounds() {
+        let src = "
+; Loads the local at the index -1 of three locals
+.proc index
+    alloc 3
+    movv 0, 10
+    movv 1, 11
+    movv 2, 12
+    ldx -1, 0, -1
+    ret
+.end
+
+; Shifts three locals down by one and adds the first two
+.proc copy
+    alloc 3
+    movv 0, 1
+    mov 1, -1
+    movv 2, 3
+    copy 0, 1, 2
+    adds -1, 0, 1
+    ret
+.end
+
+; Crosses from the parameters into the locals
+.proc fill
+    alloc 1
+    fill -1, 2, -1
+    ret
+.end
+
+; Stores the address -1 to itself and loads it back
+.proc memory
+    st64 -1, -1
+    ld64 -1, -1
+    ret
+.end
+";
+        let mut runtime = runtime_with(src, RuntimeBuilder::new().memory_size(16));
+        let cases: &[(&str, i64, Result<i64, TrapKind>)] = &[
+            ("index", 2, Ok(12)),
+            ("index", 3, Err(TrapKind::BadIndex { base: 0, index: 3 })),
+            ("index", -1, Err(TrapKind::BadIndex { base: 0, index: -1 })),
+            ("copy", 2, Ok(5)),
+            ("fill", 0, Err(TrapKind::BadIndex { base: -1, index: 1 })),
+            ("memory", 8, Ok(8)),
+            ("memory", 9, Err(TrapKind::BadAddress { addr: 9, size: 8 })),
+            (
+                "memory",
+                -1,
+                Err(TrapKind::BadAddress { addr: -1, size: 8 }),
+            ),
+        ];
+        for (proc, arg, expected) in cases {
+            assert_eq!(
+                &invoke_s64(&mut runtime, proc, &[*arg]),
+                expected,
+                "{proc} {arg}"
+            );
+        }
+    }
+
+    /// Runs `mnemonic -1, -1, -2` with `left` in -1 and `right` in -2
+    fn binary(mnemonic: &str, left: Value, right: Value) -> Result<Value, TrapKind> {
+        let src = format!(".proc main\n    {mnemonic} -1, -1, -2\n    ret\n.end\n");
+        let mut runtime = runtime(&src);
+        let result = runtime.invoke(0, &[left, right]);
+        result.map(|values| values[0]).map_err(|trap| trap.kind)
+    }
+
+    /// Runs `mnemonic -1, -1` with `value` in -1
+    fn unary(mnemonic: &str, value: Value) -> Result<Value, TrapKind> {
+        let src = format!(".proc main\n    {mnemonic} -1, -1\n    ret\n.end\n");
+        let mut runtime = runtime(&src);
+        let result = runtime.invoke(0, &[value]);
+        result.map(|values| values[0]).map_err(|trap| trap.kind)
+    }
+
+    /// Runs `mnemonic -1, -2, taken` and returns whether the branch was taken
+    fn branch(mnemonic: &str, left: Value, right: Value) -> Result<bool, TrapKind> {
+        let src = format!(
+            ".proc main\n    {mnemonic} -1, -2, taken\n    movv -1, 0\n    ret\n\
+             taken:\n    movv -1, 1\n    ret\n.end\n"
+        );
+        let mut runtime = runtime(&src);
+        let result = runtime.invoke(0, &[left, right]);
+        result
+            .map(|values| values[0] == Value::S64(1))
+            .map_err(|trap| trap.kind)
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        use Value::S64;
+        let overflow = Err(TrapKind::IntegerOverflow);
+        let cases = [
+            ("adds", S64(i64::MAX), S64(1), overflow.clone()),
+            ("adds", S64(i64::MIN), S64(-1), overflow.clone()),
+            ("adds", S64(i64::MAX), S64(i64::MIN), Ok(S64(-1))),
+            ("subs", S64(i64::MIN), S64(1), overflow.clone()),
+            ("muls", S64(i64::MIN), S64(-1), overflow.clone()),
+            ("muls", S64(1 << 32), S64(1 << 31), overflow.clone()),
+            ("divs", S64(i64::MIN), S64(-1), overflow.clone()),
+            ("divs", S64(-7), S64(2), Ok(S64(-3))),
+            ("divs", S64(1), S64(0), Err(TrapKind::DivideByZero)),
+            ("rems", S64(i64::MIN), S64(-1), overflow.clone()),
+            ("rems", S64(-7), S64(2), Ok(S64(-1))),
+            ("rems", S64(1), S64(0), Err(TrapKind::DivideByZero)),
+        ];
+        for (mnemonic, left, right, expected) in cases {
+            assert_eq!(
+                binary(mnemonic, left, right),
+                expected,
+                "{mnemonic} {left} {right}"
+            );
+        }
+    }
+
+    #[test]
+    fn verify_callee_only() {
+        let mut runtime = runtime(INVOKE);
+        // Falls off the end of the code
+        runtime.push_proc(Proc::new(vec![ALLOC, 1, 0]));
+        runtime.push_proc(Proc::new(vec![RETURN]));
+        let len = runtime.procs().len() as u32;
+        let trap = runtime.invoke(len - 2, &[]).unwrap_err();
+        assert!(matches!(trap.kind, TrapKind::Verify(_)), "{trap}");
+        // Other pending procs can still be called
+        assert_eq!(runtime.invoke(len - 1, &[]), Ok(vec![]));
+        assert!(runtime.verify().is_err());
+        assert_eq!(
+            runtime.invoke(len, &[]).unwrap_err().kind,
+            TrapKind::BadProcIndex(len)
+        );
+    }
+
+    #[test]
+    fn integer_comparisons() {
+        let pairs = [(i64::MIN, 1), (1, i64::MIN), (-1, 0), (5, 5)];
+        let cases = [
+            ("eq", "beq", [0, 0, 0, 1]),
+            ("ne", "bne", [1, 1, 1, 0]),
+            ("lt", "blt", [1, 0, 1, 0]),
+            ("le", "ble", [1, 0, 1, 1]),
+            ("gt", "bgt", [0, 1, 0, 0]),
+            ("ge", "bge", [0, 1, 0, 1]),
+            // Negative values are large as u64
+            ("ltu", "bltu", [0, 1, 0, 0]),
+            ("leu", "bleu", [0, 1, 0, 1]),
+            ("gtu", "bgtu", [1, 0, 1, 0]),
+            ("geu", "bgeu", [1, 0, 1, 1]),
+        ];
+        for (mnemonic, branch_mnemonic, expected) in cases {
+            for ((left, right), expected) in pairs.into_iter().zip(expected) {
+                let (left, right) = (Value::S64(left), Value::S64(right));
+                assert_eq!(
+                    binary(mnemonic, left, right),
+                    Ok(Value::S64(expected)),
+                    "{mnemonic} {left} {right}"
+                );
+                assert_eq!(
+                    branch(branch_mnemonic, left, right),
+                    Ok(expected == 1),
+                    "{branch_mnemonic} {left} {right}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn float_comparisons() {
+        let pairs = [
+            (1.0, 2.0),
+            (2.0, 2.0),
+            (f64::NAN, 1.0),
+            (-0.0, 0.0),
+            (f64::NAN, f64::NAN),
+        ];
+        let cases = [
+            // Only `nef` and `unof` are true for NaN
+            ("eqf", "beqf", [0, 1, 0, 1, 0]),
+            ("nef", "bnef", [1, 0, 1, 0, 1]),
+            ("ltf", "bltf", [1, 0, 0, 0, 0]),
+            ("lef", "blef", [1, 1, 0, 1, 0]),
+            ("gtf", "bgtf", [0, 0, 0, 0, 0]),
+            ("gef", "bgef", [0, 1, 0, 1, 0]),
+            ("unof", "bunof", [0, 0, 1, 0, 1]),
+        ];
+        for (mnemonic, branch_mnemonic, expected) in cases {
+            for ((left, right), expected) in pairs.into_iter().zip(expected) {
+                let (left, right) = (Value::F64(left), Value::F64(right));
+                assert_eq!(
+                    binary(mnemonic, left, right),
+                    Ok(Value::S64(expected)),
+                    "{mnemonic} {left} {right}"
+                );
+                assert_eq!(
+                    branch(branch_mnemonic, left, right),
+                    Ok(expected == 1),
+                    "{branch_mnemonic} {left} {right}"
+                );
+            }
+        }
+        assert_eq!(unary("nanf", Value::F64(f64::NAN)), Ok(Value::S64(1)));
+        assert_eq!(unary("nanf", Value::F64(f64::INFINITY)), Ok(Value::S64(0)));
+    }
+
+    #[test]
+    fn bitwise() {
+        let binary_cases = [
+            ("and", 0b1100, 0b1010, 0b1000),
+            ("or", 0b1100, 0b1010, 0b1110),
+            ("xor", 0b1100, 0b1010, 0b0110),
+            ("shl", 1, 63, i64::MIN),
+            // Shift amounts are u64, so negative amounts are 64 or more
+            ("shl", 1, 64, 0),
+            ("shl", 1, -1, 0),
+            ("shr", -1, 1, i64::MAX),
+            ("shr", -1, 64, 0),
+            ("shr", -1, -1, 0),
+            ("sar", -8, 1, -4),
+            ("sar", i64::MIN, 64, -1),
+            ("sar", i64::MIN, -1, -1),
+            ("sar", 8, 100, 0),
+            // Rotate amounts are modulo 64
+            ("rol", i64::MIN, 1, 1),
+            ("rol", 1, 64, 1),
+            ("rol", 1, -1, i64::MIN),
+            ("ror", 1, 1, i64::MIN),
+            ("ror", 1, 65, i64::MIN),
+            ("ror", i64::MIN, -1, 1),
+        ];
+        for (mnemonic, left, right, expected) in binary_cases {
+            assert_eq!(
+                binary(mnemonic, Value::S64(left), Value::S64(right)),
+                Ok(Value::S64(expected)),
+                "{mnemonic} {left} {right}"
+            );
+        }
+        let unary_cases = [
+            ("not", 0, -1),
+            ("popcnt", -1, 64),
+            ("popcnt", 0b1011, 3),
+            ("clz", 1, 63),
+            ("clz", 0, 64),
+            ("ctz", i64::MIN, 63),
+            ("ctz", 0, 64),
+        ];
+        for (mnemonic, value, expected) in unary_cases {
+            assert_eq!(
+                unary(mnemonic, Value::S64(value)),
+                Ok(Value::S64(expected)),
+                "{mnemonic} {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        use Value::{F64, S64};
+        let two_63 = 9223372036854775808.0;
+        let invalid = Err(TrapKind::InvalidConversion);
+        let cases = [
+            ("ftoi", F64(-1.9), Ok(S64(-1))),
+            // Out of range values wrap around modulo 2^64
+            ("ftoi", F64(two_63), Ok(S64(i64::MIN))),
+            ("ftoi", F64(2.0 * two_63 + 4096.0), Ok(S64(4096))),
+            ("ftoi", F64(1e20), Ok(S64(7766279631452241920))),
+            ("ftoi", F64(f64::NAN), Ok(S64(0))),
+            ("ftoi", F64(f64::NEG_INFINITY), Ok(S64(0))),
+            ("ftoi_sat", F64(-2.9), Ok(S64(-2))),
+            ("ftoi_sat", F64(two_63), Ok(S64(i64::MAX))),
+            ("ftoi_sat", F64(-1e300), Ok(S64(i64::MIN))),
+            ("ftoi_sat", F64(f64::NAN), Ok(S64(0))),
+            ("ftoi_chk", F64(-two_63), Ok(S64(i64::MIN))),
+            (
+                "ftoi_chk",
+                F64(two_63 - 1024.0),
+                Ok(S64(9223372036854774784)),
+            ),
+            ("ftoi_chk", F64(two_63), invalid.clone()),
+            ("ftoi_chk", F64(-two_63 - 2048.0), invalid.clone()),
+            ("ftoi_chk", F64(f64::NAN), invalid.clone()),
+            ("ftoi_chk", F64(f64::INFINITY), invalid),
+            ("itof", S64(i64::MAX), Ok(F64(two_63))),
+            ("itof", S64(-3), Ok(F64(-3.0))),
+            ("round", F64(2.5), Ok(F64(3.0))),
+            ("round", F64(-2.5), Ok(F64(-3.0))),
+            ("floor", F64(-1.5), Ok(F64(-2.0))),
+            ("ceil", F64(-1.5), Ok(F64(-1.0))),
+            ("trunc", F64(-1.7), Ok(F64(-1.0))),
+            ("fbits", F64(1.0), Ok(S64(0x3ff0000000000000))),
+            ("bitsf", S64(0x3ff0000000000000), Ok(F64(1.0))),
+        ];
+        for (mnemonic, value, expected) in cases {
+            assert_eq!(unary(mnemonic, value), expected, "{mnemonic} {value}");
+        }
+    }
+
+    #[test]
+    fn immediate_operands() {
+        let run = |insn: String, value: i64| {
+            let src = format!(
+                ".proc main\n    {insn}\n    movv -1, 0\n    ret\ntaken:\n    movv -1, 1\n    ret\n.end\n"
+            );
+            invoke_s64(&mut runtime(&src), "main", &[value])
+        };
+        let overflow = Err(TrapKind::IntegerOverflow);
+        let cases = [
+            ("addsi", i64::MAX, 1, overflow.clone()),
+            ("addsi", i64::MIN, -1, overflow.clone()),
+            ("addsi", i64::MAX, i32::MIN, Ok(i64::MAX + i32::MIN as i64)),
+            ("subsi", i64::MIN, 1, overflow.clone()),
+            ("subsi", -1, i32::MIN, Ok(i32::MAX as i64)),
+            ("mulsi", i64::MIN, -1, overflow.clone()),
+            ("mulsi", i64::MAX / 2 + 1, 2, overflow),
+            ("mulsi", -3, i32::MAX, Ok(-3 * i32::MAX as i64)),
+        ];
+        for (mnemonic, value, imm, expected) in cases {
+            // Returns the result before reaching the branches
+            let insn = format!("{mnemonic} -1, -1, {imm}\n    ret");
+            assert_eq!(run(insn, value), expected, "{mnemonic} {value} {imm}");
+        }
+        let branches = [
+            ("beqi", [0, 1, 0]),
+            ("bnei", [1, 0, 1]),
+            ("blti", [1, 0, 0]),
+            ("blei", [1, 1, 0]),
+            ("bgti", [0, 0, 1]),
+            ("bgei", [0, 1, 1]),
+        ];
+        for (mnemonic, expected) in branches {
+            for (value, expected) in [i64::MIN, -5, i64::MAX].into_iter().zip(expected) {
+                let insn = format!("{mnemonic} -1, -5, taken");
+                assert_eq!(run(insn, value), Ok(expected), "{mnemonic} {value} -5");
+            }
+        }
+    }
+
+    #[test]
+    fn wrapping_and_saturating() {
+        let cases = [
+            ("adds_wrap", i64::MAX, 1, i64::MIN),
+            ("adds_wrap", -1, 1, 0),
+            ("subs_wrap", i64::MIN, 1, i64::MAX),
+            ("muls_wrap", i64::MIN, -1, i64::MIN),
+            ("muls_wrap", 1 << 32, 1 << 32, 0),
+            ("adds_sat", i64::MAX, 1, i64::MAX),
+            ("adds_sat", i64::MIN, -1, i64::MIN),
+            ("subs_sat", i64::MIN, 1, i64::MIN),
+            ("subs_sat", 0, i64::MIN, i64::MAX),
+            ("muls_sat", i64::MIN, -1, i64::MAX),
+            ("muls_sat", -(1 << 32), 1 << 32, i64::MIN),
+            ("muls_sat", -3, 7, -21),
+        ];
+        for (mnemonic, left, right, expected) in cases {
+            assert_eq!(
+                binary(mnemonic, Value::S64(left), Value::S64(right)),
+                Ok(Value::S64(expected)),
+                "{mnemonic} {left} {right}"
+            );
+        }
+    }
+
+    #[test]
+    fn unsigned_and_narrow() {
+        let binary_cases = [
+            ("mulh", -1, 1, Ok(-1)),
+            ("mulh", i64::MIN, 2, Ok(-1)),
+            ("mulh", i64::MIN, i64::MIN, Ok(1 << 62)),
+            ("mulh", i64::MAX, i64::MAX, Ok((1 << 62) - 1)),
+            // Negative operands are large as u64
+            ("mulhu", -1, -1, Ok(-2)),
+            ("mulhu", -1, 2, Ok(1)),
+            ("mulhu", i64::MIN, 2, Ok(1)),
+            ("divu", -1, 2, Ok(i64::MAX)),
+            ("divu", 1, 0, Err(TrapKind::DivideByZero)),
+            ("remu", -1, 10, Ok(5)),
+            ("remu", 1, 0, Err(TrapKind::DivideByZero)),
+        ];
+        for (mnemonic, left, right, expected) in binary_cases {
+            assert_eq!(
+                binary(mnemonic, Value::S64(left), Value::S64(right)),
+                expected.map(Value::S64),
+                "{mnemonic} {left} {right}"
+            );
+        }
+        let unary_cases = [
+            ("sext8", 0xff, -1),
+            ("sext8", 0x17f, 127),
+            ("sext16", 0x8000, -32768),
+            ("sext32", 0xffff_ffff, -1),
+            ("zext8", -1, 0xff),
+            ("zext16", -1, 0xffff),
+            ("zext32", -1, 0xffff_ffff),
+        ];
+        for (mnemonic, value, expected) in unary_cases {
+            assert_eq!(
+                unary(mnemonic, Value::S64(value)),
+                Ok(Value::S64(expected)),
+                "{mnemonic} {value}"
+            );
+        }
+    }
+
+    #[test]
+    fn type_traps() {
+        use Value::{F64, S64};
+        let mismatch = |slot, expected, found| {
+            Err(TrapKind::TypeMismatch {
+                slot,
+                expected,
+                found,
+            })
+        };
+        assert_eq!(
+            binary("adds", F64(1.0), S64(1)),
+            mismatch(-1, Tag::S64, Tag::F64)
+        );
+        assert_eq!(
+            binary("adds", S64(1), F64(1.0)),
+            mismatch(-2, Tag::S64, Tag::F64)
+        );
+        assert_eq!(
+            binary("addf", S64(1), F64(1.0)),
+            mismatch(-1, Tag::F64, Tag::S64)
+        );
+        assert_eq!(
+            binary("eq", S64(1), Value::Proc(0)),
+            mismatch(-2, Tag::S64, Tag::Proc)
+        );
+        assert_eq!(unary("slen", S64(0)), mismatch(-1, Tag::Str, Tag::S64));
+
+        let src = "
+.proc main
+    alloc 2
+    movv 1, 1
+    mov -1, 0
+    ret
+.end
+
+.proc call
+    call_dyn -1
+    ret
+.end
+
+.proc host
+    alloc 1
+    mov 0, -1
+    hcall f64
+    ret
+.end
+";
+        let mut runtime = runtime(src);
+        let first = |result: Result<Vec<Value>, TrapKind>| result.map(|values| values[0]);
+        assert_eq!(
+            first(invoke(&mut runtime, "main", &[0])),
+            Err(TrapKind::Uninitialized(0))
+        );
+        assert_eq!(
+            first(invoke(&mut runtime, "call", &[0])),
+            mismatch(-1, Tag::Proc, Tag::S64)
+        );
+        // The host API checks tags as well
+        runtime.register_host("f64", |rt| rt.load_slot_f64(-1).map(|_| ()));
+        assert_eq!(
+            first(invoke(&mut runtime, "host", &[0])),
+            mismatch(-1, Tag::F64, Tag::S64)
+        );
     }
 }
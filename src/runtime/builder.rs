@@ -0,0 +1,107 @@
+use std::ptr::null;
+
+use crate::module::{format::LoadError, Module};
+
+use super::{
+    heap::{Heap, DEFAULT_MAX_HEAP_SIZE},
+    memory::Memory,
+    stack::Stack,
+    Runtime,
+};
+
+/// Default stack size in elements
+pub const DEFAULT_STACK_SIZE: usize = 4096;
+
+/// Configures a [Runtime]
+///
+/// ```
+/// use simple_vm::runtime::builder::RuntimeBuilder;
+///
+/// let runtime = RuntimeBuilder::new()
+///     .stack_size(1024)
+///     .max_stack_size(1 << 20)
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
+pub struct RuntimeBuilder {
+    stack_size: usize,
+    max_stack_size: Option<usize>,
+    max_heap_size: usize,
+    memory_size: usize,
+    catch_traps: bool,
+}
+
+impl RuntimeBuilder {
+    pub fn new() -> Self {
+        Self {
+            stack_size: DEFAULT_STACK_SIZE,
+            max_stack_size: None,
+            max_heap_size: DEFAULT_MAX_HEAP_SIZE,
+            memory_size: 0,
+            catch_traps: false,
+        }
+    }
+
+    /// Sets the initial stack size in elements
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = size;
+        self
+    }
+
+    /// Lets the stack grow up to `size` elements when it is full
+    ///
+    /// By default the stack does not grow beyond its initial size.
+    pub fn max_stack_size(mut self, size: usize) -> Self {
+        self.max_stack_size = Some(size);
+        self
+    }
+
+    /// Sets the size in values the heap may grow to
+    pub fn max_heap_size(mut self, size: usize) -> Self {
+        self.max_heap_size = size;
+        self
+    }
+
+    /// Sets the size in bytes of the linear memory, which is empty by default
+    pub fn memory_size(mut self, size: usize) -> Self {
+        self.memory_size = size;
+        self
+    }
+
+    /// Lets exception handlers catch traps like division by zero
+    ///
+    /// The handler receives the message of the trap as a string. Disabled by default.
+    pub fn catch_traps(mut self, catch: bool) -> Self {
+        self.catch_traps = catch;
+        self
+    }
+
+    pub fn build(self) -> Runtime {
+        let max_stack_size = self.max_stack_size.unwrap_or(self.stack_size);
+        Runtime {
+            pc: null(),
+            stack: Stack::growable(self.stack_size, max_stack_size),
+            heap: Heap::new(self.max_heap_size),
+            memory: Memory::new(self.memory_size),
+            constants: Vec::new(),
+            const_strs: Vec::new(),
+            procs: Vec::new(),
+            verified: Vec::new(),
+            hosts: Vec::new(),
+            catch_traps: self.catch_traps,
+            host_depth: 0,
+            thrown: None,
+        }
+    }
+
+    /// Loads and verifies a module file, see [Runtime::load]
+    pub fn load(self, bytes: &[u8]) -> Result<Runtime, LoadError> {
+        Ok(Module::from_bytes(bytes)?.into_runtime_with(self)?)
+    }
+}
+
+impl Default for RuntimeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,3 +1,4 @@
+#[cfg(feature = "debugger")]
 pub mod app;
 
 use std::{
@@ -7,12 +8,13 @@ use std::{
 
 use crate::{
     opcodes::{
-        Alloc, Call, CallDynamic, Instruction, ALLOC, BREAKPOINT, CALL, CALL_DYNAMIC, RETURN,
+        Alloc, Call, CallDynamic, Instruction, TailCall, TailDynamic, ALLOC, BREAKPOINT, CALL,
+        CALL_DYNAMIC, RETURN, TAIL_CALL, TAIL_DYNAMIC,
     },
-    value::Value,
+    value::RawValue,
 };
 
-use super::{proc::Proc, Runtime};
+use super::{proc::Proc, stack::Stack, trap::Trap, Runtime};
 
 pub struct Debugger {
     runtime: Runtime,
@@ -21,20 +23,23 @@ pub struct Debugger {
     /// Toggle for the debugger app
     paused: bool,
     finished: bool,
+    /// The trap that finished execution
+    trap: Option<Trap>,
 }
 
 impl Debugger {
-    pub fn new(mut runtime: Runtime, main: u32) -> Self {
-        runtime.call(main);
+    pub fn new(mut runtime: Runtime, main: u32) -> Result<Self, Trap> {
+        runtime.call(main)?;
         let main = &*runtime.procs[main as usize];
-        let callstack = vec![CallFrameInfo::new(main, runtime.stack.fp)];
-        Self {
+        let callstack = vec![CallFrameInfo::new(main, &runtime.stack)];
+        Ok(Self {
             runtime,
             breakpoints: HashMap::new(),
             callstack,
             paused: true,
             finished: false,
-        }
+            trap: None,
+        })
     }
 
     pub fn add_breakpoint(&mut self, proc_index: u32, offset: usize) {
@@ -45,34 +50,77 @@ impl Debugger {
         self.breakpoints.insert(address, Breakpoint::new(proc));
     }
 
-    pub fn execute(&mut self, opcode: u8) {
+    fn execute(&mut self, opcode: u8) -> Result<(), Trap> {
         debug_assert!(!self.finished);
+        let start = self.runtime.pc.wrapping_sub(1);
+        let result = match opcode {
+            ALLOC | CALL | CALL_DYNAMIC | TAIL_CALL | TAIL_DYNAMIC | RETURN | BREAKPOINT => self
+                .execute_tracked(opcode)
+                .or_else(|trap| self.runtime.trap_at(start, trap)),
+            _ => self.runtime.execute(opcode),
+        };
+        // Untrack callframes unwound by an exception handler
+        let stack = &self.runtime.stack;
+        if !stack.fp.is_null() {
+            let depth = unsafe { stack.end().offset_from(stack.fp) as usize };
+            while self
+                .callstack
+                .last()
+                .is_some_and(|frame| frame.depth > depth)
+            {
+                self.callstack.pop();
+            }
+        }
+        result
+    }
+
+    /// Executes the instructions that change the tracked callframes
+    fn execute_tracked(&mut self, opcode: u8) -> Result<(), Trap> {
         match opcode {
             ALLOC => {
-                let insn = Alloc::read(&mut self.runtime);
-                self.runtime.stack.alloc(insn.size as usize);
+                let insn = Alloc::read(&mut self.runtime.operands());
+                self.runtime.stack.alloc(insn.size as usize)?;
                 // Track frame size
                 self.callstack.last_mut().unwrap().size += insn.size as usize;
             }
             CALL => {
-                let insn = Call::read(&mut self.runtime);
-                self.runtime.call(insn.index);
+                let insn = Call::read(&mut self.runtime.operands());
+                self.runtime.call(insn.proc)?;
                 // Track callframe
                 self.callstack.push(CallFrameInfo::new(
-                    &*self.runtime.procs[insn.index as usize],
-                    self.runtime.stack.fp,
+                    &*self.runtime.procs[insn.proc as usize],
+                    &self.runtime.stack,
                 ));
             }
             CALL_DYNAMIC => {
-                let insn = CallDynamic::read(&mut self.runtime);
-                let proc = unsafe { self.runtime.stack.load(insn.src).proc };
-                unsafe { self.runtime.push_call_frame(proc) };
+                let insn = CallDynamic::read(&mut self.runtime.operands());
+                let (proc, closure) = self.runtime.load_slot_callee(insn.src)?;
+                self.runtime.call_closure(proc, closure)?;
                 // Track callframe
-                self.callstack
-                    .push(CallFrameInfo::new(proc, self.runtime.stack.fp));
+                self.callstack.push(CallFrameInfo::new(
+                    &*self.runtime.procs[proc as usize],
+                    &self.runtime.stack,
+                ));
+            }
+            TAIL_CALL => {
+                let insn = TailCall::read(&mut self.runtime.operands());
+                self.runtime.tail_call(insn.proc, None)?;
+                // Replace callframe
+                *self.callstack.last_mut().unwrap() = CallFrameInfo::new(
+                    &*self.runtime.procs[insn.proc as usize],
+                    &self.runtime.stack,
+                );
+            }
+            TAIL_DYNAMIC => {
+                let insn = TailDynamic::read(&mut self.runtime.operands());
+                let (proc, closure) = self.runtime.load_slot_callee(insn.src)?;
+                self.runtime.tail_call(proc, closure)?;
+                // Replace callframe
+                *self.callstack.last_mut().unwrap() =
+                    CallFrameInfo::new(&*self.runtime.procs[proc as usize], &self.runtime.stack);
             }
             RETURN => {
-                let ra = self.runtime.stack.return_call();
+                let ra = self.runtime.stack.return_call()?;
                 self.runtime.pc = ra;
                 // Untrack callframe
                 self.callstack.pop().unwrap();
@@ -80,14 +128,28 @@ impl Debugger {
             BREAKPOINT => {
                 self.paused = true;
             }
-            _ => self.runtime.execute(opcode),
+            _ => unreachable!("opcode {opcode} is not tracked"),
         }
+        Ok(())
     }
 
     pub fn step(&mut self) {
         debug_assert!(!self.finished);
+        if self.runtime.pc.is_null() {
+            self.finished = true;
+            return;
+        }
         let opcode = self.runtime.fetch();
-        self.execute(opcode);
+        if let Err(trap) = self.execute(opcode) {
+            self.trap = Some(trap);
+            self.finished = true;
+            self.paused = true;
+        }
+    }
+
+    /// Returns the trap that finished execution
+    pub fn trap(&self) -> Option<&Trap> {
+        self.trap.as_ref()
     }
 
     pub fn resume(&mut self, skip_first: bool) -> Option<Breakpoint> {
@@ -95,7 +157,7 @@ impl Debugger {
         if skip_first {
             self.step();
         }
-        while !self.paused && !self.runtime.pc.is_null() {
+        while !self.paused && !self.finished && !self.runtime.pc.is_null() {
             if let Some(breakpoint) = self.breakpoints.get(&self.runtime.pc) {
                 self.paused = true;
                 return Some(breakpoint.clone());
@@ -120,7 +182,7 @@ impl Debugger {
             /// Iterations between checks
             const ITERS_PER_CHECK: usize = 255;
             for _ in 0..ITERS_PER_CHECK {
-                if self.paused {
+                if self.paused || self.finished {
                     return None;
                 }
                 if self.runtime.pc.is_null() {
@@ -153,12 +215,81 @@ impl Breakpoint {
 
 pub struct CallFrameInfo {
     pub proc: *const Proc,
-    pub fp: *const Value,
+    /// Distance of the frame from the bottom of the stack, which stays the same when the
+    /// stack grows
+    pub depth: usize,
     pub size: usize,
 }
 
 impl CallFrameInfo {
-    pub fn new(proc: *const Proc, fp: *const Value) -> Self {
-        Self { proc, fp, size: 0 }
+    /// Creates the info for the current frame of `stack`
+    pub fn new(proc: *const Proc, stack: &Stack) -> Self {
+        let depth = unsafe { stack.end().offset_from(stack.fp) as usize };
+        Self {
+            proc,
+            depth,
+            size: 0,
+        }
+    }
+
+    /// Returns the frame pointer
+    pub fn fp(&self, stack: &Stack) -> *const RawValue {
+        unsafe { stack.end().sub(self.depth) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble;
+
+    use super::*;
+
+    #[test]
+    fn tail_call_callstack() {
+        let src = "
+.proc main
+    alloc 1
+    call first
+    hlt
+.end
+
+.proc first
+    alloc 2
+    tcall second
+.end
+
+.proc second
+    alloc 1
+    ret
+.end
+";
+        let runtime = assemble(src).unwrap().into_runtime().unwrap();
+        let entry = runtime.find_proc("main").unwrap();
+        let [main, first, second] = ["main", "first", "second"]
+            .map(|name| &*runtime.procs[runtime.find_proc(name).unwrap() as usize] as *const Proc);
+        let mut debugger = Debugger::new(runtime, entry).unwrap();
+        let frames = |debugger: &Debugger| {
+            debugger
+                .callstack
+                .iter()
+                .map(|frame| (frame.proc, frame.size))
+                .collect::<Vec<_>>()
+        };
+        for _ in 0..3 {
+            debugger.step();
+        }
+        assert_eq!(frames(&debugger), [(main, 1), (first, 2)]);
+        let depth = debugger.callstack[1].depth;
+        debugger.step();
+        assert_eq!(frames(&debugger), [(main, 1), (second, 0)]);
+        assert_eq!(debugger.callstack[1].depth, depth);
+        debugger.step();
+        assert_eq!(frames(&debugger), [(main, 1), (second, 1)]);
+        debugger.step();
+        assert_eq!(frames(&debugger), [(main, 1)]);
+        debugger.step();
+        debugger.step();
+        assert!(debugger.finished);
+        assert!(debugger.trap().is_none());
     }
 }
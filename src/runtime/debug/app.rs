@@ -2,6 +2,8 @@ use std::time::Duration;
 
 use eframe::egui;
 
+use crate::value::Tag;
+
 use super::Debugger;
 
 const ICON_RESUME: egui::ImageSource = egui::include_image!("../../../assets/icons/resume.png");
@@ -84,22 +86,32 @@ impl DebugApp {
 
     fn draw_central_panel(&mut self, ui: &mut egui::Ui) {
         ui.code(format!("pc: {:?}", self.debugger.runtime.pc));
+        if let Some(trap) = &self.debugger.trap {
+            ui.colored_label(egui::Color32::RED, format!("trap: {trap}"));
+        }
         let Some(frame) = self.debugger.callstack.get(self.selected_frame) else {
             return;
         };
+        let fp = frame.fp(&self.debugger.runtime.stack);
         ui.code(format!(
             "proc: {:?}
 fp: {:?}
 size: {}",
-            frame.proc, frame.fp, frame.size
+            frame.proc, fp, frame.size
         ));
+        let stack = &self.debugger.runtime.stack;
+        if let Some(closure) = stack.frame_closure(frame.depth) {
+            match self.debugger.runtime.object(closure) {
+                Ok(object) => ui.code(format!("closure: {object}")),
+                Err(trap) => ui.code(format!("closure: {trap}")),
+            };
+        }
         for offset in 0..frame.size {
-            unsafe {
-                let value = *frame.fp.sub(1 + offset);
-                let proc = value.proc;
-                let s64 = value.s64;
-                ui.code(format!("  [{offset}]: {proc:012?} ~ {s64}"));
-            }
+            let stack = &self.debugger.runtime.stack;
+            match stack.value_at(frame.depth + 1 + offset) {
+                Some(value) => ui.code(format!("  [{offset}]: {}: {value}", value.tag())),
+                None => ui.code(format!("  [{offset}]: {}", Tag::Uninit)),
+            };
         }
     }
 }
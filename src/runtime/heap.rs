@@ -0,0 +1,324 @@
+//! ## Heap
+//!
+//! Objects are addressed by [Value::Ref], [Value::Str] and [Value::Closure] handles, which
+//! index the object table. Freed entries are reused by later allocations.
+//!
+//! The heap is collected with a mark and sweep pass when its size reaches a threshold,
+//! which is doubled from the surviving size after each collection. Roots are the values on
+//! the stack, the strings of the constant pool and a thrown value unwinding through host
+//! functions, so references held only by the host may be collected.
+
+use std::fmt;
+
+use crate::value::Value;
+
+/// Default maximum heap size in values
+pub const DEFAULT_MAX_HEAP_SIZE: usize = 1 << 24;
+
+/// Heap size in values below which no collection is done
+const MIN_THRESHOLD: usize = 1024;
+
+pub enum Object {
+    /// Created by `newrec` with a fixed number of fields
+    Record(Box<[Value]>),
+    /// Created by `newarr` with a length from a slot
+    Array(Box<[Value]>),
+    /// An immutable UTF-8 string
+    Str(Box<str>),
+    /// Created by `closure` from a proc index and the captured values
+    Closure { proc: u32, captures: Box<[Value]> },
+}
+
+impl Object {
+    /// Returns the fields of a record or array or the captures of a closure, strings have none
+    pub fn fields(&self) -> &[Value] {
+        match self {
+            Object::Record(fields) | Object::Array(fields) => fields,
+            Object::Closure { captures, .. } => captures,
+            Object::Str(_) => &[],
+        }
+    }
+
+    pub fn fields_mut(&mut self) -> &mut [Value] {
+        match self {
+            Object::Record(fields) | Object::Array(fields) => fields,
+            Object::Closure { captures, .. } => captures,
+            Object::Str(_) => &mut [],
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Object::Str(str) => Some(str),
+            _ => None,
+        }
+    }
+
+    /// Returns the size in values, counting one for the object itself
+    pub fn size(&self) -> usize {
+        match self {
+            Object::Str(str) => Object::str_size(str.len()),
+            _ => 1 + self.fields().len(),
+        }
+    }
+
+    /// Returns the size in values of a string of `len` bytes
+    pub fn str_size(len: usize) -> usize {
+        1 + len.div_ceil(8)
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (open, close) = match self {
+            Object::Record(_) => ('{', '}'),
+            Object::Array(_) => ('[', ']'),
+            Object::Str(str) => return write!(f, "{str:?}"),
+            Object::Closure { proc, .. } => {
+                write!(f, "{} ", Value::Proc(*proc))?;
+                ('[', ']')
+            }
+        };
+        write!(f, "{open}")?;
+        for (i, value) in self.fields().iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{value}")?;
+        }
+        write!(f, "{close}")
+    }
+}
+
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    /// Mark bits of the running collection, indexed like `objects`
+    marks: Vec<bool>,
+    /// Indices of freed entries in `objects`
+    free: Vec<u32>,
+    /// Total size of all live objects in values
+    size: usize,
+    max_size: usize,
+    /// Size at which the next allocation collects first
+    threshold: usize,
+}
+
+impl Heap {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            objects: Vec::new(),
+            marks: Vec::new(),
+            free: Vec::new(),
+            size: 0,
+            max_size,
+            threshold: MIN_THRESHOLD,
+        }
+    }
+
+    /// Returns the total size of all objects in values
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns the number of objects
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: u32) -> Option<&Object> {
+        self.objects.get(index as usize)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, index: u32) -> Option<&mut Object> {
+        self.objects.get_mut(index as usize)?.as_mut()
+    }
+
+    /// Returns whether allocating `size` values should collect first
+    pub fn should_collect(&self, size: usize) -> bool {
+        self.size + size > self.threshold
+    }
+
+    /// Returns whether `size` more values fit into the heap
+    pub fn has_space(&self, size: usize) -> bool {
+        size <= self.max_size - self.size
+    }
+
+    /// Adds `object` and returns its index
+    ///
+    /// The caller checks [Heap::has_space] first.
+    pub fn alloc(&mut self, object: Object) -> u32 {
+        self.size += object.size();
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = Some(object);
+                index
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.marks.push(false);
+                self.objects.len() as u32 - 1
+            }
+        }
+    }
+
+    /// Frees all objects not reachable from `roots` and returns how many were freed
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = u32>) -> usize {
+        let mut pending: Vec<u32> = roots.into_iter().collect();
+        while let Some(index) = pending.pop() {
+            let Some(mark) = self.marks.get_mut(index as usize) else {
+                continue;
+            };
+            if *mark {
+                continue;
+            }
+            *mark = true;
+            if let Some(object) = &self.objects[index as usize] {
+                pending.extend(object.fields().iter().filter_map(|value| value.as_object()));
+            }
+        }
+        let mut freed = 0;
+        for (index, (object, mark)) in self.objects.iter_mut().zip(&mut self.marks).enumerate() {
+            if !std::mem::take(mark) {
+                if let Some(object) = object.take() {
+                    self.size -= object.size();
+                    self.free.push(index as u32);
+                    freed += 1;
+                }
+            }
+        }
+        self.threshold = (self.size * 2).max(MIN_THRESHOLD);
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::assemble,
+        runtime::{builder::RuntimeBuilder, trap::TrapKind},
+    };
+
+    use super::*;
+
+    fn record(len: usize) -> Object {
+        Object::Record(vec![Value::S64(0); len].into())
+    }
+
+    #[test]
+    fn collect_unreachable() {
+        let mut heap = Heap::new(DEFAULT_MAX_HEAP_SIZE);
+        let a = heap.alloc(record(1));
+        let b = heap.alloc(record(2));
+        let c = heap.alloc(record(1));
+        let d = heap.alloc(record(1));
+        heap.get_mut(a).unwrap().fields_mut()[0] = Value::Ref(c);
+        // A cycle without a root
+        heap.get_mut(b).unwrap().fields_mut()[0] = Value::Ref(d);
+        heap.get_mut(d).unwrap().fields_mut()[0] = Value::Ref(b);
+        assert_eq!(heap.size(), 9);
+        assert_eq!(heap.collect([a]), 2);
+        assert!(heap.get(a).is_some());
+        assert!(heap.get(b).is_none());
+        assert!(heap.get(c).is_some());
+        assert!(heap.get(d).is_none());
+        assert_eq!(heap.len(), 2);
+        assert_eq!(heap.size(), 4);
+        assert_eq!(heap.collect([]), 2);
+        assert!(heap.is_empty());
+        assert_eq!(heap.size(), 0);
+    }
+
+    #[test]
+    fn reuse_freed() {
+        let mut heap = Heap::new(DEFAULT_MAX_HEAP_SIZE);
+        let a = heap.alloc(record(1));
+        let b = heap.alloc(record(1));
+        heap.collect([b]);
+        assert_eq!(heap.alloc(Object::Str("reused".into())), a);
+        assert_eq!(heap.get(a).unwrap().as_str(), Some("reused"));
+        assert_eq!(heap.alloc(record(1)), 2);
+    }
+
+    #[test]
+    fn threshold() {
+        let mut heap = Heap::new(DEFAULT_MAX_HEAP_SIZE);
+        assert!(!heap.should_collect(MIN_THRESHOLD));
+        assert!(heap.should_collect(MIN_THRESHOLD + 1));
+        let big = heap.alloc(record(MIN_THRESHOLD));
+        heap.collect([big]);
+        assert_eq!(heap.threshold, 2 * (MIN_THRESHOLD + 1));
+        assert!(!heap.should_collect(MIN_THRESHOLD + 1));
+        heap.collect([]);
+        assert_eq!(heap.threshold, MIN_THRESHOLD);
+    }
+
+    #[test]
+    fn runtime_roots() {
+        let src = r#"
+.const greeting "hi"
+
+.proc main
+    alloc 4
+    newrec 0, 1                 ; held by the stack
+    newrec 1, 2                 ; captured by the closure
+    closure 2, main, 1, 1
+    ldc 3, greeting             ; cached by the constant pool
+    newrec 1, 3                 ; unreachable once overwritten
+    movv 1, 0
+    movv 3, 0
+    hcall gc
+    ret
+.end
+"#;
+        let mut runtime = assemble(src).unwrap().into_runtime().unwrap();
+        runtime.register_host("gc", |rt| {
+            assert_eq!(rt.heap().len(), 5);
+            assert_eq!(rt.collect_garbage(), 1);
+            for index in 0..4 {
+                assert!(rt.heap().get(index).is_some());
+            }
+            assert!(rt.heap().get(4).is_none());
+            assert_eq!(rt.str(3), Ok("hi"));
+            Ok(())
+        });
+        let main = runtime.find_proc("main").unwrap();
+        runtime.invoke(main, &[]).unwrap();
+        // Only the constant string is left once the stack is empty
+        assert_eq!(runtime.collect_garbage(), 3);
+        assert_eq!(runtime.heap().len(), 1);
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let src = "
+.proc main
+    alloc 2
+    newrec 0, 7
+    bz -1, keep
+    movv 0, 0
+keep:
+    newrec 1, 7
+    ret
+.end
+";
+        let builder = RuntimeBuilder::new().max_heap_size(15);
+        let mut runtime = assemble(src).unwrap().into_runtime_with(builder).unwrap();
+        let main = runtime.find_proc("main").unwrap();
+        assert_eq!(
+            runtime.invoke(main, &[Value::S64(0)]).unwrap_err().kind,
+            TrapKind::OutOfMemory
+        );
+        assert_eq!(runtime.heap().size(), 8);
+        // The first record is collected to make space
+        assert_eq!(runtime.invoke(main, &[Value::S64(1)]).map(|_| ()), Ok(()));
+        assert_eq!(runtime.heap().size(), 8);
+    }
+}
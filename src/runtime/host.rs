@@ -0,0 +1,17 @@
+use std::rc::Rc;
+
+use super::{trap::Trap, Runtime};
+
+/// A Rust function callable from guest code with `hcall`
+///
+/// The host function runs in its own frame, so its parameter and return values are in the
+/// negative slots just like for guest procs, see [Runtime::load_slot] and
+/// [Runtime::store_slot]. It can stop the program with [Runtime::halt] or by returning a
+/// [Trap], and may call back into guest code.
+pub type HostFn = dyn Fn(&mut Runtime) -> Result<(), Trap>;
+
+pub(super) struct Host {
+    pub name: String,
+    /// `None` until a function is registered under the name
+    pub func: Option<Rc<HostFn>>,
+}
@@ -0,0 +1,73 @@
+//! ## Memory
+//!
+//! A linear byte memory next to the stack and the heap for data-heavy guest code. Values
+//! are stored little-endian at byte addresses without alignment requirements.
+//!
+//! The size is set with [RuntimeBuilder::memory_size](super::builder::RuntimeBuilder::memory_size)
+//! and does not change. All bytes start out as zero.
+
+pub struct Memory {
+    bytes: Box<[u8]>,
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        Self {
+            bytes: vec![0; size].into_boxed_slice(),
+        }
+    }
+
+    /// Returns the size in bytes
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    /// Reads the `N` bytes at `addr`, or returns [None] if they are out of bounds
+    #[inline]
+    pub fn read<const N: usize>(&self, addr: i64) -> Option<[u8; N]> {
+        let range = self.range(addr, N)?;
+        Some(self.bytes[range].try_into().unwrap())
+    }
+
+    /// Writes `bytes` at `addr`, or returns [None] if they are out of bounds
+    #[inline]
+    pub fn write<const N: usize>(&mut self, addr: i64, bytes: [u8; N]) -> Option<()> {
+        let range = self.range(addr, N)?;
+        self.bytes[range].copy_from_slice(&bytes);
+        Some(())
+    }
+
+    #[inline]
+    fn range(&self, addr: i64, len: usize) -> Option<std::ops::Range<usize>> {
+        let start = usize::try_from(addr).ok()?;
+        let end = start.checked_add(len)?;
+        (end <= self.bytes.len()).then_some(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range() {
+        let mut memory = Memory::new(16);
+        assert_eq!(memory.write(8, [1; 8]), Some(()));
+        assert_eq!(memory.read::<8>(8), Some([1; 8]));
+        assert_eq!(memory.read::<2>(7), Some([0, 1]));
+        assert_eq!(memory.read::<0>(16), Some([]));
+        assert_eq!(memory.read::<8>(9), None);
+        assert_eq!(memory.write(15, [0; 2]), None);
+        assert_eq!(memory.read::<1>(-1), None);
+        assert_eq!(memory.read::<8>(i64::MAX), None);
+        assert_eq!(Memory::new(0).read::<1>(0), None);
+    }
+}
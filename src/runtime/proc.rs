@@ -8,17 +8,56 @@ macro_rules! proc {
             $(
                 $crate::opcodes::Instruction::write(&$insn, &mut code);
             )*
-            $crate::proc::Proc::new(code)
+            $crate::runtime::proc::Proc::new(code)
         }
     };
 }
 
 pub struct Proc {
+    /// Name used for diagnostics, if known
+    pub name: Option<String>,
     pub code: Box<[u8]>,
+    /// Exception table, searched in order so inner regions must come first
+    pub handlers: Vec<Handler>,
 }
 
 impl Proc {
     pub fn new(code: impl Into<Box<[u8]>>) -> Self {
-        Self { code: code.into() }
+        Self {
+            name: None,
+            code: code.into(),
+            handlers: Vec::new(),
+        }
+    }
+
+    pub fn named(name: impl Into<String>, code: impl Into<Box<[u8]>>) -> Self {
+        Self {
+            name: Some(name.into()),
+            code: code.into(),
+            handlers: Vec::new(),
+        }
     }
+
+    /// Returns the first handler whose region contains the byte at `offset`
+    pub fn find_handler(&self, offset: usize) -> Option<&Handler> {
+        self.handlers
+            .iter()
+            .find(|handler| (handler.start as usize..handler.end as usize).contains(&offset))
+    }
+}
+
+/// An entry of the exception table of a [Proc]
+///
+/// Values thrown by instructions in `start..end`, including those thrown by callees, are
+/// caught by the handler at `target`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handler {
+    /// Byte offset of the first instruction of the region
+    pub start: u32,
+    /// Byte offset after the last instruction of the region
+    pub end: u32,
+    /// Byte offset of the handler
+    pub target: u32,
+    /// Slot that receives the thrown value
+    pub slot: i16,
 }
@@ -1,7 +1,8 @@
-use std::mem::MaybeUninit;
-use std::ptr::null_mut;
+use std::fmt;
+use std::mem::{offset_of, size_of, MaybeUninit};
+use std::ptr::{self, null_mut};
 
-use crate::value::Value;
+use crate::value::{RawValue, Tag, Value};
 
 /// ## Stack layout
 ///
@@ -19,17 +20,43 @@ use crate::value::Value;
 /// The slot `-1` is the parameter or return slot closest to the top of the stack.
 ///
 /// All slots are local to the current function call.
+///
+/// Slot accesses are checked against the current frame: locals against the slots
+/// reserved with [Stack::alloc] and parameters against the locals of the caller.
+///
+/// A range of `len` slots starts at a slot and counts up from it. Ranges may not cross
+/// from the parameters into the locals.
+///
+/// ## Tags
+///
+/// Each element has a [Tag] in a shadow array next to the values, so loads return a typed
+/// [Value]. Allocated elements start out as [Tag::Uninit] and can't be loaded until written.
+///
+/// ## Growing
+///
+/// A stack with a maximum size above its initial size is moved to a larger buffer when it is
+/// full. All pointers into the old buffer, including the caller frames saved in each
+/// [StackFrame], are rebased onto the new one.
 #[repr(C)]
 pub struct Stack {
     /// Stack top
-    pub(super) sp: *mut Value,
+    pub(super) sp: *mut RawValue,
     /// Call frame
-    pub(super) fp: *mut Value,
-    owner: Box<[MaybeUninit<Value>]>,
+    pub(super) fp: *mut RawValue,
+    owner: Box<[MaybeUninit<RawValue>]>,
+    /// Tag of each element of `owner`
+    tags: Box<[Tag]>,
+    /// Size the stack may grow to
+    max_size: usize,
 }
 
 impl Stack {
     pub fn new(size: usize) -> Self {
+        Self::growable(size, size)
+    }
+
+    /// Creates a stack of `size` elements that can grow up to `max_size` elements
+    pub fn growable(size: usize, max_size: usize) -> Self {
         unsafe {
             let mut owner = Box::new_uninit_slice(size);
             let sp = owner.as_mut_ptr().add(size) as _;
@@ -37,70 +64,462 @@ impl Stack {
                 sp,
                 fp: null_mut(),
                 owner,
+                tags: vec![Tag::Uninit; size].into_boxed_slice(),
+                max_size: max_size.max(size),
+            }
+        }
+    }
+
+    /// Returns the current size in elements
+    pub fn size(&self) -> usize {
+        self.owner.len()
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns the end of the buffer, which is the bottom of the stack
+    pub fn end(&self) -> *const RawValue {
+        unsafe { self.owner.as_ptr().add(self.owner.len()) as _ }
+    }
+
+    /// Returns the current position of `sp` and `fp`
+    pub fn mark(&self) -> Mark {
+        unsafe {
+            Mark {
+                sp: self.end().offset_from(self.sp) as usize,
+                fp: (!self.fp.is_null()).then(|| self.end().offset_from(self.fp) as usize),
+            }
+        }
+    }
+
+    /// Moves `sp` and `fp` back to `mark`, discarding everything pushed since
+    ///
+    /// # Safety
+    ///
+    /// `mark` must have been created by this stack and no frame that existed at that time may
+    /// have been popped since.
+    pub unsafe fn reset(&mut self, mark: Mark) {
+        unsafe {
+            let end = self.end() as *mut RawValue;
+            self.sp = end.sub(mark.sp);
+            self.fp = match mark.fp {
+                Some(fp) => end.sub(fp),
+                None => null_mut(),
+            };
+        }
+    }
+
+    /// Pops all frames and elements
+    pub fn clear(&mut self) {
+        self.sp = self.end() as *mut RawValue;
+        self.fp = null_mut();
+    }
+
+    /// Moves the stack to a buffer with space for at least `n` more elements
+    #[cold]
+    fn grow(&mut self, n: usize) -> Result<(), StackError> {
+        let used = self.owner.len() - self.free();
+        let required = used.checked_add(n).ok_or(StackError::Overflow)?;
+        if required > self.max_size {
+            return Err(StackError::Overflow);
+        }
+        let size = self
+            .owner
+            .len()
+            .saturating_mul(2)
+            .clamp(required, self.max_size);
+        unsafe {
+            let mut owner = Box::<[RawValue]>::new_uninit_slice(size);
+            let old_end = self.end();
+            let new_end = owner.as_mut_ptr().add(size) as *mut RawValue;
+            ptr::copy_nonoverlapping(self.sp, new_end.sub(used), used);
+            let mut tags = vec![Tag::Uninit; size].into_boxed_slice();
+            tags[size - used..].copy_from_slice(&self.tags[self.tags.len() - used..]);
+            let rebase = |ptr: *mut RawValue| {
+                if ptr.is_null() {
+                    return ptr;
+                }
+                new_end.sub(old_end.offset_from(ptr) as usize)
+            };
+            self.sp = rebase(self.sp);
+            self.fp = rebase(self.fp);
+            let mut frame = self.fp as *mut StackFrame;
+            while !frame.is_null() {
+                (*frame).fp = rebase((*frame).fp);
+                frame = (*frame).fp as *mut StackFrame;
             }
+            self.owner = owner;
+            self.tags = tags;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of elements on the stack
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.owner.len() - self.free()
+    }
+
+    /// Returns the number of free elements below `sp`
+    #[inline]
+    pub fn free(&self) -> usize {
+        unsafe { self.sp.offset_from(self.owner.as_ptr() as *const RawValue) as usize }
+    }
+
+    /// Returns the number of local slots in the current frame
+    #[inline]
+    pub fn frame_size(&self) -> usize {
+        if self.fp.is_null() {
+            return 0;
+        }
+        unsafe { self.fp.offset_from(self.sp) as usize }
+    }
+
+    /// Returns the number of parameter slots accessible from the current frame
+    ///
+    /// These are the locals of the caller or, for the first frame, everything above it.
+    #[inline]
+    pub fn params_size(&self) -> usize {
+        if self.fp.is_null() {
+            return 0;
+        }
+        unsafe {
+            let caller = (*(self.fp as *mut StackFrame)).fp;
+            let end = if caller.is_null() { self.end() } else { caller };
+            end.offset_from(self.fp.add(FRAME_SIZE)) as usize
+        }
+    }
+
+    /// Returns the closure the current frame was called with
+    pub fn closure(&self) -> Option<u32> {
+        if self.fp.is_null() {
+            return None;
+        }
+        self.frame_closure(unsafe { self.end().offset_from(self.fp) as usize })
+    }
+
+    /// Returns the closure of the frame `depth` elements from the bottom of the stack
+    pub fn frame_closure(&self, depth: usize) -> Option<u32> {
+        let depth = depth.checked_sub(offset_of!(StackFrame, closure) / size_of::<RawValue>())?;
+        match self.value_at(depth)? {
+            Value::Closure(index) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Sets the closure of the current frame, which has none when it is pushed
+    pub fn set_closure(&mut self, closure: u32) {
+        assert!(!self.fp.is_null());
+        unsafe {
+            let frame = self.fp as *mut StackFrame;
+            self.write(ptr::addr_of_mut!((*frame).closure), Value::Closure(closure));
         }
     }
 
     /// Reserves size for `n` elements
     #[inline]
-    pub fn alloc(&mut self, n: usize) {
+    pub fn alloc(&mut self, n: usize) -> Result<(), StackError> {
+        if n > self.free() {
+            self.grow(n)?;
+        }
         unsafe {
             self.sp = self.sp.sub(n);
         }
+        let start = self.index(self.sp);
+        self.tags[start..start + n].fill(Tag::Uninit);
+        Ok(())
     }
 
-    /// Loads the value at the given stack slot
+    /// Returns the index of `ptr` in the buffer
     #[inline]
-    pub fn load(&mut self, slot: i16) -> Value {
+    fn index(&self, ptr: *const RawValue) -> usize {
+        unsafe { ptr.offset_from(self.owner.as_ptr() as *const RawValue) as usize }
+    }
+
+    /// Checks that `slot` is inside the current frame
+    #[inline]
+    pub fn check_slot(&self, slot: i16) -> Result<(), StackError> {
+        let in_frame = if slot < 0 {
+            (-(slot as isize)) as usize <= self.params_size()
+        } else {
+            (slot as usize) < self.frame_size()
+        };
+        if !in_frame {
+            return Err(StackError::BadSlot(slot));
+        }
+        Ok(())
+    }
+
+    /// Checks that `slot` is inside the current frame if it is a parameter slot
+    ///
+    /// Local slots are not checked, see [Stack::check_slot].
+    #[inline]
+    pub fn check_param(&self, slot: i16) -> Result<(), StackError> {
+        if slot < 0 && (-(slot as isize)) as usize > self.params_size() {
+            return Err(StackError::BadSlot(slot));
+        }
+        Ok(())
+    }
+
+    /// Returns the local slot `index` slots after `base`
+    ///
+    /// Both `base` and the result must be locals of the current frame.
+    #[inline]
+    pub fn index_slot(&self, base: i16, index: i64) -> Result<i16, StackError> {
+        let slot = (base as i64)
+            .checked_add(index)
+            .filter(|&slot| base >= 0 && slot >= 0 && (slot as u64) < self.frame_size() as u64)
+            .ok_or(StackError::BadIndex { base, index })?;
+        Ok(slot as i16)
+    }
+
+    /// Checks that the `len` slots starting at `slot` are inside the current frame
+    pub fn check_range(&self, slot: i16, len: usize) -> Result<(), StackError> {
+        let Some(last) = len.checked_sub(1) else {
+            return Ok(());
+        };
+        let in_frame = i16::try_from(slot as i64 + last as i64)
+            .is_ok_and(|last| (slot < 0) == (last < 0) && self.check_slot(last).is_ok());
+        self.check_slot(slot)?;
+        if !in_frame {
+            return Err(StackError::BadIndex {
+                base: slot,
+                index: last as i64,
+            });
+        }
+        Ok(())
+    }
+
+    /// Copies the `len` slots starting at `src` to the slots starting at `dst`
+    ///
+    /// The ranges may overlap. Uninitialized slots stay uninitialized.
+    pub fn copy(&mut self, dst: i16, src: i16, len: usize) -> Result<(), StackError> {
+        self.check_range(dst, len)?;
+        self.check_range(src, len)?;
+        if len == 0 {
+            return Ok(());
+        }
         unsafe {
-            if slot < 0 {
-                return *self.fp.add(1 + (-slot) as usize);
-            }
-            *self.fp.sub(1 + slot as usize)
+            // Slots count down in memory, so the last slot has the lowest address
+            let src = self.slot_ptr(src).sub(len - 1);
+            let dst = self.slot_ptr(dst).sub(len - 1);
+            let (src_index, dst_index) = (self.index(src), self.index(dst));
+            ptr::copy(src, dst, len);
+            self.tags.copy_within(src_index..src_index + len, dst_index);
+        }
+        Ok(())
+    }
+
+    /// Stores `value` in the `len` slots starting at `dst`
+    pub fn fill(&mut self, dst: i16, len: usize, value: Value) -> Result<(), StackError> {
+        self.check_range(dst, len)?;
+        for slot in 0..len {
+            unsafe { self.store_unchecked(dst + slot as i16, value) };
         }
+        Ok(())
+    }
+
+    /// Loads the value at the given stack slot
+    #[inline]
+    pub fn load(&self, slot: i16) -> Result<Value, StackError> {
+        self.check_slot(slot)?;
+        unsafe { self.load_unchecked(slot) }
     }
 
     /// Stores a value at the given stack slot
     #[inline]
-    pub fn store(&mut self, slot: i16, value: Value) {
+    pub fn store(&mut self, slot: i16, value: Value) -> Result<(), StackError> {
+        self.check_slot(slot)?;
+        unsafe { self.store_unchecked(slot, value) };
+        Ok(())
+    }
+
+    /// Loads the value at the given stack slot without bounds checks
+    ///
+    /// # Safety
+    ///
+    /// `slot` must be inside the current frame, see [Stack::check_slot].
+    #[inline]
+    pub unsafe fn load_unchecked(&self, slot: i16) -> Result<Value, StackError> {
+        unsafe {
+            self.read(self.slot_ptr(slot))
+                .ok_or(StackError::Uninitialized(slot))
+        }
+    }
+
+    /// Stores a value at the given stack slot without bounds checks
+    ///
+    /// # Safety
+    ///
+    /// `slot` must be inside the current frame, see [Stack::check_slot].
+    #[inline]
+    pub unsafe fn store_unchecked(&mut self, slot: i16, value: Value) {
+        unsafe { self.write(self.slot_ptr(slot), value) }
+    }
+
+    /// Returns the value `depth` elements from the bottom of the stack
+    ///
+    /// Returns [None] if the element is not on the stack or uninitialized.
+    pub fn value_at(&self, depth: usize) -> Option<Value> {
+        if depth == 0 || depth > self.depth() {
+            return None;
+        }
+        unsafe { self.read(self.end().sub(depth)) }
+    }
+
+    /// Returns all initialized values on the stack
+    pub fn values(&self) -> impl Iterator<Item = Value> + '_ {
+        (1..=self.depth()).filter_map(|depth| self.value_at(depth))
+    }
+
+    /// Stores a value `depth` elements from the bottom of the stack
+    pub(super) fn store_at(&mut self, depth: usize, value: Value) {
+        assert!(depth > 0 && depth <= self.depth());
+        unsafe { self.write(self.end().sub(depth) as *mut RawValue, value) }
+    }
+
+    #[inline]
+    unsafe fn slot_ptr(&self, slot: i16) -> *mut RawValue {
         unsafe {
             if slot < 0 {
-                *self.fp.add(1 + (-slot) as usize) = value;
-                return;
+                return self.fp.add(FRAME_SIZE - 1 + (-(slot as isize)) as usize);
             }
-            *self.fp.sub(1 + slot as usize) = value;
+            self.fp.sub(1 + slot as usize)
+        }
+    }
+
+    #[inline]
+    unsafe fn read(&self, ptr: *const RawValue) -> Option<Value> {
+        unsafe {
+            Some(match *self.tags.get_unchecked(self.index(ptr)) {
+                Tag::Uninit => return None,
+                Tag::S64 => Value::S64((*ptr).s64),
+                Tag::F64 => Value::F64((*ptr).f64),
+                Tag::Proc => Value::Proc((*ptr).proc),
+                Tag::Ref => Value::Ref((*ptr).object),
+                Tag::Str => Value::Str((*ptr).object),
+                Tag::Closure => Value::Closure((*ptr).object),
+            })
+        }
+    }
+
+    #[inline]
+    unsafe fn write(&mut self, ptr: *mut RawValue, value: Value) {
+        unsafe {
+            *ptr = value.to_raw();
+            *self.tags.get_unchecked_mut(self.index(ptr)) = value.tag();
         }
     }
 
     /// Pushes a new [StackFrame] with the given return address
     #[inline]
-    pub fn push_frame(&mut self, ra: *const u8) {
+    pub fn push_frame(&mut self, ra: *const u8) -> Result<(), StackError> {
+        // Allocate first, growing may move the current frame
+        self.alloc(FRAME_SIZE)?;
+        let old_fp = self.fp;
+        self.fp = self.sp;
         unsafe {
-            let old_fp = self.fp;
-            self.alloc(2);
-            self.fp = self.sp;
             let fp = self.fp as *mut StackFrame;
             (*fp).fp = old_fp;
             (*fp).ra = ra;
         }
+        Ok(())
+    }
+
+    /// Discards the locals and the closure of the current frame for a tail call
+    ///
+    /// The caller frame, the return address and the parameters are kept.
+    #[inline]
+    pub fn reuse_frame(&mut self) {
+        assert!(!self.fp.is_null());
+        self.sp = self.fp;
+        unsafe {
+            let frame = self.fp as *mut StackFrame;
+            let closure = self.index(ptr::addr_of!((*frame).closure));
+            self.tags[closure] = Tag::Uninit;
+        }
+    }
+
+    /// Returns the return addresses of all frames, starting with the current one
+    pub fn return_addresses(&self) -> impl Iterator<Item = *const u8> + '_ {
+        let mut frame = self.fp as *const StackFrame;
+        std::iter::from_fn(move || {
+            if frame.is_null() {
+                return None;
+            }
+            unsafe {
+                let ra = (*frame).ra;
+                frame = (*frame).fp as *const StackFrame;
+                Some(ra)
+            }
+        })
     }
 
     /// Pops the current [StackFrame] and returns the return address
     #[inline]
-    pub fn return_call(&mut self) -> *const u8 {
+    pub fn return_call(&mut self) -> Result<*const u8, StackError> {
+        if self.fp.is_null() {
+            return Err(StackError::Underflow);
+        }
         unsafe {
             let fp = self.fp as *mut StackFrame;
-            self.sp = self.fp.add(2);
+            self.sp = self.fp.add(FRAME_SIZE);
             self.fp = (*fp).fp;
-            (*fp).ra
+            Ok((*fp).ra)
+        }
+    }
+}
+
+/// A position of `sp` and `fp` that stays valid when the stack grows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mark {
+    /// Distance of `sp` from the end of the stack
+    sp: usize,
+    /// Distance of `fp` from the end of the stack
+    fp: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackError {
+    /// The stack has no space left
+    Overflow,
+    /// There is no frame to return from
+    Underflow,
+    /// The slot is outside of the current frame
+    BadSlot(i16),
+    /// The slot was not written since it was allocated
+    Uninitialized(i16),
+    /// The slot `index` slots after `base` is outside of the current frame
+    BadIndex { base: i16, index: i64 },
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::Overflow => write!(f, "stack overflow"),
+            StackError::Underflow => write!(f, "stack underflow"),
+            StackError::BadSlot(slot) => write!(f, "slot {slot} is outside of the frame"),
+            StackError::Uninitialized(slot) => write!(f, "slot {slot} is uninitialized"),
+            StackError::BadIndex { base, index } => {
+                write!(f, "slot {base} + {index} is outside of the frame")
+            }
         }
     }
 }
 
+impl std::error::Error for StackError {}
+
+/// Number of elements of a [StackFrame]
+pub const FRAME_SIZE: usize = size_of::<StackFrame>() / size_of::<RawValue>();
+
 /// ## Stack frame layout
 ///
 /// ```text
 /// +=========================+
+/// | Closure                 |
+/// +-------------------------+
 /// | Return address          |
 /// +-------------------------+
 /// | Caller frame            |
@@ -117,7 +536,171 @@ impl Stack {
 #[repr(C)]
 pub struct StackFrame {
     /// Caller frame
-    fp: *mut Value,
+    fp: *mut RawValue,
     /// Return address
     ra: *const u8,
+    /// Closure of the called proc, tagged like a slot and uninitialized for plain calls
+    closure: RawValue,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::null;
+
+    use crate::{
+        asm::assemble,
+        runtime::{builder::RuntimeBuilder, trap::TrapKind},
+    };
+
+    use super::*;
+
+    /// Returns `1 + 2 + ... + n` recursively, using `FRAME_SIZE + 1` elements per call
+    const SUM: &str = "
+.proc sum
+    alloc 1
+    bz -1, done
+    subsi 0, -1, 1
+    call sum
+    adds -1, -1, 0
+done:
+    ret
+.end
+";
+
+    fn sum(builder: RuntimeBuilder, n: i64) -> Result<Vec<Value>, TrapKind> {
+        let mut runtime = assemble(SUM).unwrap().into_runtime_with(builder).unwrap();
+        let sum = runtime.find_proc("sum").unwrap();
+        runtime
+            .invoke(sum, &[Value::S64(n)])
+            .map_err(|trap| trap.kind)
+    }
+
+    #[test]
+    fn return_call() {
+        let mut stack = Stack::new(16);
+        let ra = 8 as *const u8;
+        stack.push_frame(null()).unwrap();
+        stack.push_frame(ra).unwrap();
+        assert_eq!(stack.return_call(), Ok(ra));
+        assert_eq!(stack.return_call(), Ok(null()));
+        assert_eq!(stack.depth(), 0);
+        assert_eq!(stack.return_call(), Err(StackError::Underflow));
+    }
+
+    #[test]
+    fn grow() {
+        let mut stack = Stack::growable(4, 64);
+        stack.push_frame(null()).unwrap();
+        stack.alloc(1).unwrap();
+        stack.store(0, Value::S64(7)).unwrap();
+        stack.push_frame(null()).unwrap();
+        assert_eq!(stack.size(), 8);
+        // Grows to at least the required size
+        stack.alloc(40).unwrap();
+        assert_eq!(stack.size(), 47);
+        assert_eq!(stack.alloc(18), Err(StackError::Overflow));
+        stack.alloc(17).unwrap();
+        assert_eq!(stack.size(), 64);
+        // The caller frame was rebased
+        assert_eq!(stack.return_call(), Ok(null()));
+        assert_eq!(stack.load(0), Ok(Value::S64(7)));
+    }
+
+    #[test]
+    fn grow_recursion() {
+        let builder = RuntimeBuilder::new().stack_size(16).max_stack_size(1 << 16);
+        assert_eq!(sum(builder, 1000), Ok(vec![Value::S64(500500)]));
+    }
+
+    #[test]
+    fn overflow_at_max() {
+        let builder = RuntimeBuilder::new().stack_size(16).max_stack_size(256);
+        assert_eq!(sum(builder.clone(), 50), Ok(vec![Value::S64(1275)]));
+        assert_eq!(sum(builder, 100), Err(TrapKind::StackOverflow));
+    }
+
+    /// Returns a stack with a caller frame of 4 locals and a current frame of 3 locals
+    fn frames() -> Stack {
+        let mut stack = Stack::new(16);
+        stack.push_frame(null()).unwrap();
+        stack.alloc(4).unwrap();
+        stack.push_frame(null()).unwrap();
+        stack.alloc(3).unwrap();
+        stack
+    }
+
+    #[test]
+    fn index_slot() {
+        let stack = frames();
+        let bad = |base, index| Err(StackError::BadIndex { base, index });
+        assert_eq!(stack.index_slot(0, 2), Ok(2));
+        assert_eq!(stack.index_slot(2, -2), Ok(0));
+        assert_eq!(stack.index_slot(1, 2), bad(1, 2));
+        assert_eq!(stack.index_slot(0, -1), bad(0, -1));
+        // Parameters can't be indexed
+        assert_eq!(stack.index_slot(-2, 1), bad(-2, 1));
+        assert_eq!(stack.index_slot(0, i64::MAX), bad(0, i64::MAX));
+        assert_eq!(
+            stack.index_slot(i16::MAX, i64::MIN),
+            bad(i16::MAX, i64::MIN)
+        );
+    }
+
+    #[test]
+    fn check_range() {
+        let stack = frames();
+        assert_eq!(stack.check_range(0, 3), Ok(()));
+        assert_eq!(stack.check_range(-4, 4), Ok(()));
+        assert_eq!(stack.check_range(3, 0), Ok(()));
+        assert_eq!(
+            stack.check_range(1, 3),
+            Err(StackError::BadIndex { base: 1, index: 2 })
+        );
+        // Ranges may not cross from the parameters into the locals
+        assert_eq!(
+            stack.check_range(-1, 2),
+            Err(StackError::BadIndex { base: -1, index: 1 })
+        );
+        assert_eq!(stack.check_range(-5, 1), Err(StackError::BadSlot(-5)));
+        assert_eq!(
+            stack.check_range(i16::MAX, 2),
+            Err(StackError::BadSlot(i16::MAX))
+        );
+    }
+
+    #[test]
+    fn copy_overlapping() {
+        let mut stack = frames();
+        let values = |stack: &Stack| (0..3).map(|slot| stack.load(slot)).collect::<Vec<_>>();
+        stack.store(0, Value::S64(1)).unwrap();
+        stack.store(1, Value::S64(2)).unwrap();
+        // Uninitialized slots are copied as such
+        stack.copy(0, 1, 2).unwrap();
+        assert_eq!(
+            values(&stack),
+            [
+                Ok(Value::S64(2)),
+                Err(StackError::Uninitialized(1)),
+                Err(StackError::Uninitialized(2))
+            ]
+        );
+        stack.store(1, Value::S64(3)).unwrap();
+        // Overlapping ranges are copied as if through a buffer
+        stack.copy(1, 0, 2).unwrap();
+        assert_eq!(
+            values(&stack),
+            [Ok(Value::S64(2)), Ok(Value::S64(2)), Ok(Value::S64(3))]
+        );
+        stack.fill(-4, 4, Value::S64(4)).unwrap();
+        stack.copy(0, -3, 3).unwrap();
+        assert_eq!(values(&stack), [Ok(Value::S64(4)); 3]);
+        assert_eq!(
+            stack.copy(-2, 0, 3),
+            Err(StackError::BadIndex { base: -2, index: 2 })
+        );
+        assert_eq!(
+            stack.fill(2, 2, Value::S64(0)),
+            Err(StackError::BadIndex { base: 2, index: 1 })
+        );
+    }
 }
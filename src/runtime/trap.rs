@@ -0,0 +1,201 @@
+use std::fmt;
+
+use crate::{
+    value::{Tag, Value},
+    verify::VerifyError,
+};
+
+use super::stack::StackError;
+
+/// An error raised while executing guest code
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trap {
+    pub kind: TrapKind,
+    /// Where the trap happened, if it was raised by an instruction
+    pub location: Option<Location>,
+}
+
+impl Trap {
+    pub fn new(kind: TrapKind) -> Self {
+        Self {
+            kind,
+            location: None,
+        }
+    }
+
+    /// Creates a trap for a host function to return
+    pub fn host(message: impl Into<String>) -> Self {
+        Self::new(TrapKind::Host(message.into()))
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(location) = &self.location {
+            write!(f, " in {location}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Trap {}
+
+impl From<TrapKind> for Trap {
+    fn from(value: TrapKind) -> Self {
+        Self::new(value)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TrapKind {
+    InvalidOpcode(u8),
+    DivideByZero,
+    IntegerOverflow,
+    /// An f64 is NaN or out of range for the integer it is converted to
+    InvalidConversion,
+    StackOverflow,
+    /// A return without a frame to return from
+    StackUnderflow,
+    /// The slot is outside of the current frame
+    BadSlot(i16),
+    /// The slot was not written since it was allocated
+    Uninitialized(i16),
+    /// The slot `index` slots after `base` is outside of the frame
+    BadIndex {
+        base: i16,
+        index: i64,
+    },
+    /// The slot holds a value of another type
+    TypeMismatch {
+        slot: i16,
+        expected: Tag,
+        found: Tag,
+    },
+    BadProcIndex(u32),
+    /// A called proc failed verification
+    Verify(VerifyError),
+    /// `ldcap` in a proc that was not called through a closure
+    NoClosure,
+    /// The referenced object was collected
+    BadRef(u32),
+    /// A negative array length
+    BadLength(i64),
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    /// A string index is not on a char boundary
+    NotCharBoundary(i64),
+    /// A substring ends before it starts
+    BadRange {
+        start: i64,
+        end: i64,
+    },
+    /// A string does not parse as a number
+    InvalidNumber(String),
+    /// An access of `size` bytes at `addr` is outside of the linear memory
+    BadAddress {
+        addr: i64,
+        size: usize,
+    },
+    /// The heap is full even after collecting garbage
+    OutOfMemory,
+    BadConstIndex(u32),
+    BadHostIndex(u32),
+    /// No function was registered for the host function
+    UnboundHost(String),
+    /// Raised by a host function
+    Host(String),
+    /// A host function returned without its frame on top of the stack
+    HostFrame,
+    /// Raised by `throw`, [Runtime::run](super::Runtime::run) unwinds to a handler
+    Thrown(Value),
+    /// A thrown value that no handler caught, rendered as text
+    Uncaught(String),
+    /// The program halted before an invoked proc returned
+    Halted,
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapKind::InvalidOpcode(opcode) => write!(f, "invalid opcode [0x{opcode:02x}]"),
+            TrapKind::DivideByZero => write!(f, "division by zero"),
+            TrapKind::IntegerOverflow => write!(f, "integer overflow"),
+            TrapKind::InvalidConversion => write!(f, "invalid conversion to integer"),
+            TrapKind::StackOverflow => write!(f, "stack overflow"),
+            TrapKind::StackUnderflow => write!(f, "stack underflow"),
+            TrapKind::BadSlot(slot) => write!(f, "slot {slot} is outside of the frame"),
+            TrapKind::Uninitialized(slot) => write!(f, "slot {slot} is uninitialized"),
+            TrapKind::BadIndex { base, index } => {
+                write!(f, "slot {base} + {index} is outside of the frame")
+            }
+            TrapKind::TypeMismatch {
+                slot,
+                expected,
+                found,
+            } => write!(f, "slot {slot} holds {found}, expected {expected}"),
+            TrapKind::BadProcIndex(index) => write!(f, "proc #{index} does not exist"),
+            TrapKind::Verify(err) => write!(f, "{err}"),
+            TrapKind::NoClosure => write!(f, "the proc was not called through a closure"),
+            TrapKind::BadRef(index) => write!(f, "object #{index} does not exist"),
+            TrapKind::BadLength(len) => write!(f, "invalid array length {len}"),
+            TrapKind::IndexOutOfBounds { index, len } => {
+                write!(f, "index {index} out of bounds for length {len}")
+            }
+            TrapKind::NotCharBoundary(index) => {
+                write!(f, "index {index} is not on a char boundary")
+            }
+            TrapKind::BadRange { start, end } => write!(f, "invalid range {start}..{end}"),
+            TrapKind::InvalidNumber(text) => write!(f, "invalid number {text:?}"),
+            TrapKind::BadAddress { addr, size } => {
+                write!(
+                    f,
+                    "access of {size} bytes at address {addr} is out of bounds"
+                )
+            }
+            TrapKind::OutOfMemory => write!(f, "out of memory"),
+            TrapKind::BadConstIndex(index) => write!(f, "constant #{index} does not exist"),
+            TrapKind::BadHostIndex(index) => write!(f, "host function #{index} does not exist"),
+            TrapKind::UnboundHost(name) => write!(f, "host function `{name}` is not registered"),
+            TrapKind::Host(message) => write!(f, "{message}"),
+            TrapKind::HostFrame => write!(f, "host function did not return to its frame"),
+            TrapKind::Thrown(value) => write!(f, "thrown {value}"),
+            TrapKind::Uncaught(value) => write!(f, "uncaught exception {value}"),
+            TrapKind::Halted => write!(f, "halted before the invoked proc returned"),
+        }
+    }
+}
+
+impl From<StackError> for Trap {
+    fn from(value: StackError) -> Self {
+        Self::new(match value {
+            StackError::Overflow => TrapKind::StackOverflow,
+            StackError::Underflow => TrapKind::StackUnderflow,
+            StackError::BadSlot(slot) => TrapKind::BadSlot(slot),
+            StackError::Uninitialized(slot) => TrapKind::Uninitialized(slot),
+            StackError::BadIndex { base, index } => TrapKind::BadIndex { base, index },
+        })
+    }
+}
+
+/// The instruction that raised a [Trap]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// Index of the proc in the proc table
+    pub proc: u32,
+    pub name: Option<String>,
+    /// Byte offset of the instruction in the proc
+    pub offset: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "proc `{name}`")?,
+            None => write!(f, "proc #{}", self.proc)?,
+        }
+        write!(f, " at {:04x}", self.offset)
+    }
+}
@@ -36,6 +36,10 @@ pub trait Write {
     fn write_u64(&mut self, num: u64) {
         self.write(&num.to_ne_bytes());
     }
+
+    fn write_f64(&mut self, num: f64) {
+        self.write(&num.to_ne_bytes());
+    }
 }
 
 impl Write for Vec<u8> {
@@ -1,21 +1,151 @@
-use crate::runtime::proc::Proc;
+use std::fmt;
 
 #[macro_export]
 macro_rules! value {
     (@s64 $value: expr) => {
-        $crate::value::Value { s64: $value }
+        $crate::value::Value::S64($value)
     };
     (@f64 $value: expr) => {
-        $crate::value::Value { f64: $value }
+        $crate::value::Value::F64($value)
     };
     (@proc $value: expr) => {
-        $crate::value::Value { proc: $value }
+        $crate::value::Value::Proc($value)
     };
+    (@ref $value: expr) => {
+        $crate::value::Value::Ref($value)
+    };
+    (@str $value: expr) => {
+        $crate::value::Value::Str($value)
+    };
+    (@closure $value: expr) => {
+        $crate::value::Value::Closure($value)
+    };
+}
+
+/// The value of an initialized stack slot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    S64(i64),
+    F64(f64),
+    /// An index into the proc table
+    Proc(u32),
+    /// A reference to an object on the [Heap](crate::runtime::heap::Heap)
+    Ref(u32),
+    /// A reference to a string on the [Heap](crate::runtime::heap::Heap)
+    Str(u32),
+    /// A reference to a closure on the [Heap](crate::runtime::heap::Heap)
+    Closure(u32),
+}
+
+impl Value {
+    pub fn tag(self) -> Tag {
+        match self {
+            Value::S64(_) => Tag::S64,
+            Value::F64(_) => Tag::F64,
+            Value::Proc(_) => Tag::Proc,
+            Value::Ref(_) => Tag::Ref,
+            Value::Str(_) => Tag::Str,
+            Value::Closure(_) => Tag::Closure,
+        }
+    }
+
+    pub fn as_s64(self) -> Option<i64> {
+        match self {
+            Value::S64(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(self) -> Option<f64> {
+        match self {
+            Value::F64(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_proc(self) -> Option<u32> {
+        match self {
+            Value::Proc(index) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the index of the referenced object, string or closure
+    pub fn as_object(self) -> Option<u32> {
+        match self {
+            Value::Ref(index) | Value::Str(index) | Value::Closure(index) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the untagged representation
+    pub fn to_raw(self) -> RawValue {
+        match self {
+            Value::S64(s64) => RawValue { s64 },
+            Value::F64(f64) => RawValue { f64 },
+            Value::Proc(proc) => RawValue { proc },
+            Value::Ref(object) | Value::Str(object) | Value::Closure(object) => RawValue { object },
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self::S64(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Self::F64(value)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::S64(value) => write!(f, "{value}"),
+            Value::F64(value) => write!(f, "{value}"),
+            Value::Proc(index) => write!(f, "<proc #{index}>"),
+            Value::Ref(index) => write!(f, "<ref #{index}>"),
+            Value::Str(index) => write!(f, "<str #{index}>"),
+            Value::Closure(index) => write!(f, "<closure #{index}>"),
+        }
+    }
+}
+
+/// The type of the value in a stack slot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tag {
+    /// The slot was not written since it was allocated
+    Uninit,
+    S64,
+    F64,
+    Proc,
+    Ref,
+    Str,
+    Closure,
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Tag::Uninit => "uninitialized",
+            Tag::S64 => "s64",
+            Tag::F64 => "f64",
+            Tag::Proc => "proc",
+            Tag::Ref => "ref",
+            Tag::Str => "str",
+            Tag::Closure => "closure",
+        })
+    }
 }
 
+/// A [Value] without its [Tag], as stored on the [Stack](crate::runtime::stack::Stack)
 #[derive(Clone, Copy)]
-pub union Value {
+pub union RawValue {
     pub s64: i64,
     pub f64: f64,
-    pub proc: *const Proc,
+    pub proc: u32,
+    pub object: u32,
 }
@@ -0,0 +1,438 @@
+//! Static checks that make procs safe to execute.
+//!
+//! The verifier decodes every instruction of a proc and checks that
+//!
+//! - all opcodes are known and no instruction is truncated,
+//! - branch targets land on instruction boundaries inside the proc,
+//! - exception handler regions and targets lie on instruction boundaries,
+//! - proc, constant and host function indices are in the runtime tables,
+//! - local slots are below the frame size reserved by preceding `alloc`s,
+//! - the frame size is the same on every path reaching an instruction, counting the
+//!   instructions of a handler region as paths to the handler, and
+//! - every path ends in `ret`, a tail call or `hlt` instead of falling off the end of the proc.
+//!
+//! Parameter slots (`< 0`) depend on the caller and are only checked at runtime.
+
+use std::fmt;
+
+use crate::{
+    module::Module,
+    opcodes::{
+        decode_all, DecodeError, Decoded, Listing, OperandKind, ALLOC, BRANCH, HALT, RETURN,
+        TAIL_CALL, TAIL_DYNAMIC, THROW,
+    },
+    runtime::{proc::Proc, Runtime},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyError {
+    /// Index of the proc in the proc table
+    pub proc: u32,
+    /// Byte offset of the offending instruction
+    pub offset: usize,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyErrorKind {
+    Decode(DecodeError),
+    /// The proc contains no instructions
+    Empty,
+    /// The branch target is outside the proc or inside an instruction
+    BadBranchTarget(i64),
+    BadProcIndex(u32),
+    BadConstIndex(u32),
+    BadHostIndex(u32),
+    /// A local slot at or above the reserved frame size
+    BadSlot {
+        slot: i16,
+        frame_size: usize,
+    },
+    /// Two paths reach the instruction with different frame sizes
+    InconsistentFrame {
+        expected: usize,
+        found: usize,
+    },
+    /// Execution can continue past the end of the proc
+    FallsOffEnd,
+    /// The region or target of the exception handler is outside the proc or inside an
+    /// instruction
+    BadHandler(usize),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "proc #{} at {:04x}: ", self.proc, self.offset)?;
+        match &self.kind {
+            VerifyErrorKind::Decode(err) => write!(f, "{err}"),
+            VerifyErrorKind::Empty => write!(f, "empty proc"),
+            VerifyErrorKind::BadBranchTarget(target) => write!(f, "bad branch target {target}"),
+            VerifyErrorKind::BadProcIndex(index) => write!(f, "proc #{index} does not exist"),
+            VerifyErrorKind::BadConstIndex(index) => {
+                write!(f, "constant #{index} does not exist")
+            }
+            VerifyErrorKind::BadHostIndex(index) => {
+                write!(f, "host function #{index} does not exist")
+            }
+            VerifyErrorKind::BadSlot { slot, frame_size } => {
+                write!(f, "slot {slot} is outside the frame of size {frame_size}")
+            }
+            VerifyErrorKind::InconsistentFrame { expected, found } => write!(
+                f,
+                "frame size {found} differs from {expected} on another path"
+            ),
+            VerifyErrorKind::FallsOffEnd => write!(f, "execution falls off the end of the proc"),
+            VerifyErrorKind::BadHandler(index) => {
+                write!(f, "exception handler #{index} has a bad region or target")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Verifies all procs of `module`
+pub fn verify_module(module: &Module) -> Result<(), VerifyError> {
+    let tables = Tables {
+        procs: module.procs.len(),
+        constants: module.constants.len(),
+        hosts: module.hosts.len(),
+    };
+    for (index, proc) in module.procs.iter().enumerate() {
+        verify_proc(proc, index as u32, &tables)?;
+    }
+    Ok(())
+}
+
+/// Verifies all procs of `runtime`
+pub fn verify_runtime(runtime: &Runtime) -> Result<(), VerifyError> {
+    let tables = Tables {
+        procs: runtime.procs().len(),
+        constants: runtime.constants().len(),
+        hosts: runtime.host_names().len(),
+    };
+    for (index, proc) in runtime.procs().enumerate() {
+        verify_proc(proc, index as u32, &tables)?;
+    }
+    Ok(())
+}
+
+/// Sizes of the tables indices are checked against
+pub struct Tables {
+    pub procs: usize,
+    pub constants: usize,
+    pub hosts: usize,
+}
+
+/// Verifies `proc`, whose index in the proc table is `index`
+pub fn verify_proc(proc: &Proc, index: u32, tables: &Tables) -> Result<(), VerifyError> {
+    let error = |offset, kind| VerifyError {
+        proc: index,
+        offset,
+        kind,
+    };
+    let Listing { insns, error: err } = decode_all(&proc.code);
+    if let Some((offset, err)) = err {
+        return Err(error(offset, VerifyErrorKind::Decode(err)));
+    }
+    if insns.is_empty() {
+        return Err(error(0, VerifyErrorKind::Empty));
+    }
+    let position = |target: i64| {
+        insns
+            .binary_search_by_key(&target, |(offset, _)| *offset as i64)
+            .ok()
+    };
+
+    // Indices and branch targets
+    let mut targets = Vec::with_capacity(insns.len());
+    for (offset, insn) in &insns {
+        for (operand, &value) in insn.info.operands.iter().zip(&insn.operands) {
+            let kind = match operand.kind {
+                OperandKind::Proc if value as usize >= tables.procs => {
+                    VerifyErrorKind::BadProcIndex(value as u32)
+                }
+                OperandKind::Constant if value as usize >= tables.constants => {
+                    VerifyErrorKind::BadConstIndex(value as u32)
+                }
+                OperandKind::Host if value as usize >= tables.hosts => {
+                    VerifyErrorKind::BadHostIndex(value as u32)
+                }
+                _ => continue,
+            };
+            return Err(error(*offset, kind));
+        }
+        let target = match insn.branch_target(*offset) {
+            Some(target) => match position(target) {
+                Some(target) => Some(target),
+                None => return Err(error(*offset, VerifyErrorKind::BadBranchTarget(target))),
+            },
+            None => None,
+        };
+        targets.push(target);
+    }
+
+    // Exception handlers as instruction index ranges and targets
+    let mut handlers = Vec::with_capacity(proc.handlers.len());
+    for (index, handler) in proc.handlers.iter().enumerate() {
+        let boundary = |offset: u32| {
+            (offset as usize == proc.code.len())
+                .then_some(insns.len())
+                .or_else(|| position(offset as i64))
+        };
+        let region = boundary(handler.start).zip(boundary(handler.end));
+        match (region, position(handler.target as i64)) {
+            (Some((start, end)), Some(target)) if start <= end => {
+                handlers.push((start..end, target, handler.slot))
+            }
+            _ => {
+                let kind = VerifyErrorKind::BadHandler(index);
+                return Err(error(handler.start as usize, kind));
+            }
+        }
+    }
+
+    // Frame sizes along all paths
+    let mut frames = vec![None; insns.len()];
+    let mut pending = vec![(0, 0)];
+    while let Some((at, frame_size)) = pending.pop() {
+        let (offset, insn) = &insns[at];
+        match frames[at] {
+            Some(expected) if expected == frame_size => continue,
+            Some(expected) => {
+                let kind = VerifyErrorKind::InconsistentFrame {
+                    expected,
+                    found: frame_size,
+                };
+                return Err(error(*offset, kind));
+            }
+            None => frames[at] = Some(frame_size),
+        }
+        check_slots(insn, frame_size).map_err(|kind| error(*offset, kind))?;
+        for (region, target, slot) in &handlers {
+            if region.contains(&at) {
+                check_slot(*slot, frame_size).map_err(|kind| error(*offset, kind))?;
+                pending.push((*target, frame_size));
+            }
+        }
+        let frame_size = match insn.info.opcode {
+            ALLOC => frame_size + insn.operands[0] as usize,
+            _ => frame_size,
+        };
+        if let Some(target) = targets[at] {
+            pending.push((target, frame_size));
+        }
+        if !matches!(
+            insn.info.opcode,
+            BRANCH | RETURN | THROW | TAIL_CALL | TAIL_DYNAMIC | HALT
+        ) {
+            if at + 1 == insns.len() {
+                return Err(error(*offset, VerifyErrorKind::FallsOffEnd));
+            }
+            pending.push((at + 1, frame_size));
+        }
+    }
+    Ok(())
+}
+
+fn check_slots(insn: &Decoded, frame_size: usize) -> Result<(), VerifyErrorKind> {
+    for (operand, &value) in insn.info.operands.iter().zip(&insn.operands) {
+        if operand.kind == OperandKind::Slot {
+            check_slot(value as i16, frame_size)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_slot(slot: i16, frame_size: usize) -> Result<(), VerifyErrorKind> {
+    if slot >= 0 && slot as usize >= frame_size {
+        return Err(VerifyErrorKind::BadSlot { slot, frame_size });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::assemble,
+        opcodes::{DecodeError, ALLOC, HALT, RETURN},
+        runtime::proc::Handler,
+    };
+
+    use super::*;
+
+    fn verify(src: &str) -> Result<(), VerifyError> {
+        verify_module(&assemble(src).unwrap())
+    }
+
+    fn verify_code(code: Vec<u8>) -> Result<(), VerifyError> {
+        let tables = Tables {
+            procs: 1,
+            constants: 0,
+            hosts: 0,
+        };
+        verify_proc(&Proc::new(code), 0, &tables)
+    }
+
+    fn error(offset: usize, kind: VerifyErrorKind) -> Result<(), VerifyError> {
+        Err(VerifyError {
+            proc: 0,
+            offset,
+            kind,
+        })
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(verify_code(vec![HALT]), Ok(()));
+        assert_eq!(
+            verify_code(vec![0xff]),
+            error(0, VerifyErrorKind::Decode(DecodeError::UnknownOpcode(0xff)))
+        );
+        assert_eq!(
+            verify_code(vec![RETURN, ALLOC, 0]),
+            error(1, VerifyErrorKind::Decode(DecodeError::Truncated(ALLOC)))
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(verify_code(vec![RETURN]), Ok(()));
+        assert_eq!(verify_code(vec![]), error(0, VerifyErrorKind::Empty));
+    }
+
+    #[test]
+    fn bad_branch_target() {
+        let src = ".proc main\n    b done\ndone:\n    ret\n.end\n";
+        assert_eq!(verify(src), Ok(()));
+        // Into the middle of the branch itself
+        let src = ".proc main\n    b -3\n    ret\n.end\n";
+        assert_eq!(verify(src), error(0, VerifyErrorKind::BadBranchTarget(2)));
+        // Past the end of the proc
+        let src = ".proc main\n    b 1\n    ret\n.end\n";
+        assert_eq!(verify(src), error(0, VerifyErrorKind::BadBranchTarget(6)));
+    }
+
+    #[test]
+    fn bad_proc_index() {
+        assert_eq!(verify(".proc main\n    call main\n    ret\n.end\n"), Ok(()));
+        assert_eq!(
+            verify(".proc main\n    call 1\n    ret\n.end\n"),
+            error(0, VerifyErrorKind::BadProcIndex(1))
+        );
+    }
+
+    #[test]
+    fn bad_const_index() {
+        let src = ".const one 1\n.proc main\n    alloc 1\n    ldc 0, one\n    ret\n.end\n";
+        assert_eq!(verify(src), Ok(()));
+        let src = ".const one 1\n.proc main\n    alloc 1\n    ldc 0, 1\n    ret\n.end\n";
+        assert_eq!(verify(src), error(3, VerifyErrorKind::BadConstIndex(1)));
+    }
+
+    #[test]
+    fn bad_host_index() {
+        assert_eq!(
+            verify(".proc main\n    hcall print\n    ret\n.end\n"),
+            Ok(())
+        );
+        assert_eq!(
+            verify(".proc main\n    hcall 0\n    ret\n.end\n"),
+            error(0, VerifyErrorKind::BadHostIndex(0))
+        );
+    }
+
+    #[test]
+    fn bad_slot() {
+        // Parameter slots are only checked at runtime
+        let src = ".proc main\n    alloc 1\n    movv 0, 1\n    movv -5, 1\n    ret\n.end\n";
+        assert_eq!(verify(src), Ok(()));
+        let src = ".proc main\n    alloc 1\n    movv 1, 1\n    ret\n.end\n";
+        assert_eq!(
+            verify(src),
+            error(
+                3,
+                VerifyErrorKind::BadSlot {
+                    slot: 1,
+                    frame_size: 1
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn inconsistent_frame() {
+        let src = "
+.proc main
+    alloc 1
+    movv 0, 0
+    bz 0, other
+    alloc 1
+    b done
+other:
+    alloc 1
+done:
+    ret
+.end
+";
+        assert_eq!(verify(src), Ok(()));
+        let src = "
+.proc main
+    alloc 1
+    movv 0, 0
+    bz 0, done
+    alloc 1
+done:
+    ret
+.end
+";
+        assert_eq!(
+            verify(src),
+            error(
+                24,
+                VerifyErrorKind::InconsistentFrame {
+                    expected: 2,
+                    found: 1
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn falls_off_end() {
+        assert_eq!(verify(".proc main\n    tcall main\n.end\n"), Ok(()));
+        assert_eq!(
+            verify(".proc main\n    alloc 1\n.end\n"),
+            error(0, VerifyErrorKind::FallsOffEnd)
+        );
+    }
+
+    #[test]
+    fn bad_handler() {
+        let src = "
+.proc main
+    alloc 1
+    .catch try, done, failed, 0
+try:
+    call main
+done:
+    ret
+failed:
+    ret
+.end
+";
+        assert_eq!(verify(src), Ok(()));
+        let mut module = assemble(".proc main\n    alloc 1\n    ret\n.end\n").unwrap();
+        module.procs[0].handlers.push(Handler {
+            start: 0,
+            end: 3,
+            // Inside `alloc`
+            target: 1,
+            slot: 0,
+        });
+        assert_eq!(
+            verify_module(&module),
+            error(0, VerifyErrorKind::BadHandler(0))
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Runs the `svm` binary and checks its output and exit codes.
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+/// Runs `svm` with `args` from the crate root
+fn svm(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_svm"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap()
+}
+
+/// Writes `src` to a file named after the test in the temp directory
+fn program(name: &str, src: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("svm-{}-{name}.sasm", std::process::id()));
+    fs::write(&path, src).unwrap();
+    path
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Prints -1 divided by -2 and returns
+const DIVIDE: &str = "
+.proc main
+    divs -1, -1, -2
+    print_s64 -1
+    ret
+.end
+";
+
+#[test]
+fn example() {
+    let output = svm(&["run", "examples/fibonacci.sasm"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "4181\n");
+}
+
+#[test]
+fn args_and_traps() {
+    let path = program("divide", DIVIDE);
+    let path = path.to_str().unwrap();
+
+    let output = svm(&["run", path, "7", "2"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "3\n");
+
+    let output = svm(&["run", path, "7", "0"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).starts_with("trap: division by zero"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = svm(&["run", path, "7", "x"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("invalid argument `x`"),
+        "{}",
+        stderr(&output)
+    );
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn memory() {
+    let src = "
+.proc main
+    alloc 1
+    movv 0, 0
+    st64 0, 0
+    ret
+.end
+";
+    let path = program("memory", src);
+    let path = path.to_str().unwrap();
+    assert_eq!(svm(&["run", "--memory", "8", path]).status.code(), Some(0));
+    assert_eq!(svm(&["run", "--memory", "7", path]).status.code(), Some(1));
+    assert_eq!(svm(&["run", path]).status.code(), Some(1));
+
+    let output = svm(&["run", "--memory", "-1", path]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr(&output).contains("invalid memory size `-1`"),
+        "{}",
+        stderr(&output)
+    );
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn usage() {
+    for args in [
+        &[][..],
+        &["exec", "examples/fibonacci.sasm"],
+        &["run"],
+        &["run", "--memory", "8"],
+    ] {
+        let output = svm(args);
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        assert!(stderr(&output).starts_with("usage: svm run"), "{args:?}");
+    }
+    let output = svm(&["run", "examples/missing.sasm"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("examples/missing.sasm: "));
+}